@@ -0,0 +1,63 @@
+#![no_main]
+
+use arbitrary::Arbitrary;
+use buddy_allocator::BuddyAllocator;
+use libfuzzer_sys::fuzz_target;
+use std::alloc::{alloc, dealloc, Layout};
+use std::collections::BTreeMap;
+
+const HEAP_SIZE: usize = 1 << 18;
+
+#[derive(Arbitrary, Debug)]
+enum Op {
+    Alloc { size: u16, align_log: u8 },
+    Free { index: u16 },
+}
+
+fuzz_target!(|ops: Vec<Op>| {
+    let space_layout = Layout::from_size_align(HEAP_SIZE, HEAP_SIZE).unwrap();
+    let space = unsafe { alloc(space_layout) } as usize;
+    let mut heap = BuddyAllocator::empty();
+    unsafe { heap.add_to_heap(space, space + HEAP_SIZE) };
+    let initial = heap.stats();
+
+    // start of each block handed out, with the end of what was asked for and its layout
+    let mut live: BTreeMap<usize, (usize, Layout)> = BTreeMap::new();
+    for op in ops {
+        match op {
+            Op::Alloc { size, align_log } => {
+                let align = 1 << (align_log % 13);
+                let layout = Layout::from_size_align(size as usize + 1, align).unwrap();
+                let ptr = heap.alloc(layout) as usize;
+                if ptr == 0 {
+                    continue;
+                }
+                let end = ptr + layout.size();
+                assert_eq!(ptr % align, 0);
+                assert!(space <= ptr && end <= space + HEAP_SIZE);
+                // no block is handed out twice
+                if let Some((_, (prev_end, _))) = live.range(..end).next_back() {
+                    assert!(*prev_end <= ptr, "{:#x} overlaps a block in use", ptr);
+                }
+                live.insert(ptr, (end, layout));
+            }
+            Op::Free { index } => {
+                if live.is_empty() {
+                    continue;
+                }
+                let ptr = *live.keys().nth(index as usize % live.len()).unwrap();
+                let (_, layout) = live.remove(&ptr).unwrap();
+                heap.dealloc(ptr as *mut u8, layout);
+            }
+        }
+    }
+
+    // freeing everything restores the original free lists
+    for (ptr, (_, layout)) in live {
+        heap.dealloc(ptr as *mut u8, layout);
+    }
+    let stats = heap.stats();
+    assert_eq!(stats.free_blocks, initial.free_blocks);
+    assert_eq!((stats.user, stats.allocated), (0, 0));
+    unsafe { dealloc(space as *mut u8, space_layout) };
+});
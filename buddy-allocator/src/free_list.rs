@@ -0,0 +1,73 @@
+use core::ptr::null_mut;
+
+struct Node {
+    prev: *mut Node,
+    next: *mut Node,
+}
+
+/// Doubly linked list of free blocks kept inside the blocks,
+/// so a block is taken out of the middle in constant time.
+#[derive(Clone, Copy)]
+pub struct FreeList {
+    head: *mut Node,
+    len: usize,
+}
+
+unsafe impl Send for FreeList {}
+
+impl FreeList {
+    pub const fn new() -> Self {
+        Self { head: null_mut(), len: 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.is_null()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// # Safety
+    ///
+    /// `block` must be free, at least two words long and in no list.
+    pub unsafe fn push(&mut self, block: usize) {
+        let node = block as *mut Node;
+        unsafe {
+            node.write(Node { prev: null_mut(), next: self.head });
+            if !self.head.is_null() {
+                (*self.head).prev = node;
+            }
+        }
+        self.head = node;
+        self.len += 1;
+    }
+
+    pub fn pop(&mut self) -> Option<usize> {
+        if self.is_empty() {
+            return None;
+        }
+        let block = self.head as usize;
+        unsafe { self.remove(block) };
+        Some(block)
+    }
+
+    /// # Safety
+    ///
+    /// `block` must be in this list.
+    pub unsafe fn remove(&mut self, block: usize) {
+        let node = block as *mut Node;
+        unsafe {
+            let (prev, next) = ((*node).prev, (*node).next);
+            if prev.is_null() {
+                self.head = next;
+            } else {
+                (*prev).next = next;
+            }
+            if !next.is_null() {
+                (*next).prev = prev;
+            }
+        }
+        self.len -= 1;
+    }
+}
@@ -1,144 +1,379 @@
 #![no_std]
 
+mod free_list;
 mod linked_list;
 mod math;
+mod slab;
 
 use spin::{Mutex, MutexGuard};
 use core::alloc::{GlobalAlloc, Layout};
 use core::cmp::{max, min};
-use linked_list::LinkedList;
+use core::ptr::null_mut;
+use free_list::FreeList;
 use math::prev_power_of_two;
+use slab::{SlabAllocator, CACHE_COUNT};
+
+pub use slab::CacheStats;
 
 const BLOCK_LEVEL: usize = 32;
-const UNIT_SIZE: usize = size_of::<usize>();
+/// a free block holds the two links of its free list
+const UNIT_SIZE: usize = 2 * size_of::<usize>();
+const MIN_LEVEL: usize = UNIT_SIZE.trailing_zeros() as usize;
+/// separate ranges given by `add_to_heap`, blocks of different ones are never merged
+const MAX_REGIONS: usize = 32;
+
+/// A range of the heap with a bit for each pair of buddies on each level,
+/// set when exactly one of them is free. The bitmap takes the start of the range.
+#[derive(Clone, Copy)]
+struct Region {
+    start: usize,
+    end: usize,
+    map: *mut u8,
+    /// bytes in free blocks
+    free: usize,
+    /// first bit of each level
+    offsets: [u32; BLOCK_LEVEL],
+}
+
+impl Region {
+    const fn empty() -> Self {
+        Self {
+            start: 0,
+            end: 0,
+            map: core::ptr::null_mut(),
+            free: 0,
+            offsets: [0; BLOCK_LEVEL],
+        }
+    }
+
+    /// Pairs of buddies on `level` that blocks in [start, end) belong to.
+    fn pairs(start: usize, end: usize, level: usize) -> usize {
+        if end - start < 1 << level {
+            return 0;
+        }
+        ((end - 1) >> (level + 1)) - (start >> (level + 1)) + 1
+    }
+
+    /// Take the bitmap of [start, end) from its start, None if no space is left after it.
+    unsafe fn new(start: usize, end: usize) -> Option<Self> {
+        let mut region = Self::empty();
+        let mut bits = 0;
+        for level in MIN_LEVEL..BLOCK_LEVEL {
+            region.offsets[level] = bits as u32;
+            bits += Self::pairs(start, end, level);
+        }
+        let map_size = (bits.div_ceil(8) + UNIT_SIZE - 1) & !(UNIT_SIZE - 1);
+        if start + map_size >= end {
+            return None;
+        }
+        region.map = start as *mut u8;
+        region.start = start + map_size;
+        region.end = end;
+        unsafe { region.map.write_bytes(0, map_size) };
+        Some(region)
+    }
+
+    fn contains(&self, addr: usize) -> bool {
+        self.start <= addr && addr < self.end
+    }
+
+    fn bit(&self, level: usize, block: usize) -> (usize, u8) {
+        let map_start = self.map as usize;
+        let idx = self.offsets[level] as usize + (block >> (level + 1)) - (map_start >> (level + 1));
+        (idx / 8, 1 << (idx % 8))
+    }
+
+    /// Whether the buddy of a block not free is free
+    fn buddy_free(&self, level: usize, block: usize) -> bool {
+        let (byte, mask) = self.bit(level, block);
+        unsafe { *self.map.add(byte) & mask != 0 }
+    }
+
+    /// Called whenever a block becomes free or stops being free.
+    fn toggle(&self, level: usize, block: usize) {
+        let (byte, mask) = self.bit(level, block);
+        unsafe { *self.map.add(byte) ^= mask };
+    }
+}
+
+/// Heap usage, sizes in bytes.
+#[derive(Clone, Copy, Debug)]
+pub struct HeapStats {
+    /// added to the heap, not counting the bitmaps
+    pub total: usize,
+    /// asked for by the allocations
+    pub user: usize,
+    /// taken by the allocations, rounded up to blocks
+    pub allocated: usize,
+    /// free blocks of 2^i bytes at index i
+    pub free_blocks: [usize; BLOCK_LEVEL],
+}
 
 pub struct BuddyAllocator {
-    free_list: [LinkedList; BLOCK_LEVEL],
+    free_list: [FreeList; BLOCK_LEVEL],
+    regions: [Region; MAX_REGIONS],
+    region_count: usize,
     total: usize,
     user: usize,
     allocated: usize,
 }
 
+unsafe impl Send for BuddyAllocator {}
+
 impl BuddyAllocator {
     pub const fn empty() -> Self {
         Self {
-            free_list: [LinkedList::new(); BLOCK_LEVEL],
+            free_list: [FreeList::new(); BLOCK_LEVEL],
+            regions: [Region::empty(); MAX_REGIONS],
+            region_count: 0,
             total: 0,
             user: 0,
             allocated: 0,
         }
     }
 
-    // add [start, end) space to heap (aligned)
-    unsafe fn add_to_heap(&mut self, mut start: usize, mut end: usize) {
+    /// Add [start, end) space to heap (aligned), the start of it keeps the bitmap of the range.
+    /// Return false if it is too small for its bitmap or no more ranges can be added.
+    ///
+    /// # Safety
+    ///
+    /// The range must be valid memory owned by nothing else.
+    pub unsafe fn add_to_heap(&mut self, mut start: usize, mut end: usize) -> bool {
         // align
         start = (start + UNIT_SIZE - 1) & !(UNIT_SIZE - 1);
         end = end & !(UNIT_SIZE - 1);
         assert!(start <= end);
+        if self.regions_left() == 0 {
+            return false;
+        }
+        let Some(region) = (unsafe { Region::new(start, end) }) else {
+            return false;
+        };
+        let idx = self.region_count;
+        self.regions[idx] = region;
+        self.region_count += 1;
         // split space
+        for (level, block) in Self::split_space(region.start, end) {
+            self.push_free(idx, level, block);
+        }
+        assert_eq!(end - region.start, self.regions[idx].free);
+        self.total += end - region.start;
+        true
+    }
+
+    /// How many more ranges can be added.
+    pub fn regions_left(&self) -> usize {
+        MAX_REGIONS - self.region_count
+    }
+
+    /// The largest aligned blocks [start, end) is made up of, as levels and addresses.
+    fn split_space(start: usize, end: usize) -> impl Iterator<Item = (usize, usize)> {
         let mut current_start = start;
-        let mut total = 0;
-        while current_start < end {
+        core::iter::from_fn(move || {
+            if current_start >= end {
+                return None;
+            }
             let low_bit = current_start & (!current_start + 1);
             let size = min(low_bit, prev_power_of_two(end - current_start));
-            unsafe { self.free_list[size.trailing_zeros() as usize].push(current_start as *mut usize); }
+            let block = current_start;
             current_start += size;
-            total += size;
+            Some((size.trailing_zeros() as usize, block))
+        })
+    }
+
+    /// Take a range added after the first `keep` ones out of the heap if all of it is free,
+    /// return it with its bitmap so it can be given back.
+    pub fn remove_free_region(&mut self, keep: usize) -> Option<(usize, usize)> {
+        let idx = (keep..self.region_count)
+            .rev()
+            .find(|&i| self.regions[i].free == self.regions[i].end - self.regions[i].start)?;
+        let region = self.regions[idx];
+        // all merged, so the free blocks are the ones it was split into
+        for (level, block) in Self::split_space(region.start, region.end) {
+            self.remove_free(idx, level, block);
         }
-        assert_eq!(end - start, total);
-        self.total += total;
+        self.regions.copy_within(idx + 1..self.region_count, idx);
+        self.region_count -= 1;
+        self.total -= region.end - region.start;
+        Some((region.map as usize, region.end))
     }
     
     pub unsafe fn init(&mut self, start: usize, heap_size: usize) {
         unsafe { self.add_to_heap(start, start + heap_size); }
     }
-    
-    fn split(&mut self, from: usize, to: usize) {
-        for i in (to + 1 ..=from).rev() {
-            if let Some(block) = self.free_list[i].pop() {
-                unsafe { 
-                    self.free_list[i - 1].push(block);
-                    self.free_list[i - 1].push((block as usize + (1 << (i - 1))) as *mut usize);
-                }
-            } else {
-                panic!("[kernel] (Buddy Allocator) Failed to split block!");
-            }
+
+    pub fn stats(&self) -> HeapStats {
+        let mut free_blocks = [0; BLOCK_LEVEL];
+        for (count, list) in free_blocks.iter_mut().zip(self.free_list.iter()) {
+            *count = list.len();
         }
+        HeapStats {
+            total: self.total,
+            user: self.user,
+            allocated: self.allocated,
+            free_blocks,
+        }
+    }
+
+    fn region_of(&self, addr: usize) -> usize {
+        self.regions[..self.region_count]
+            .iter()
+            .position(|region| region.contains(addr))
+            .unwrap_or_else(|| panic!("[kernel] (Buddy Allocator) {:#x} is not in the heap!", addr))
+    }
+
+    fn push_free(&mut self, region: usize, level: usize, block: usize) {
+        unsafe { self.free_list[level].push(block) };
+        self.regions[region].toggle(level, block);
+        self.regions[region].free += 1 << level;
+    }
+
+    fn remove_free(&mut self, region: usize, level: usize, block: usize) {
+        unsafe { self.free_list[level].remove(block) };
+        self.regions[region].toggle(level, block);
+        self.regions[region].free -= 1 << level;
     }
     
+    /// Return null when no block is big enough.
     pub fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        self.alloc_from_free_list(layout).unwrap_or(null_mut())
+    }
+    
+    /// Return None instead of panicking when no block is big enough.
+    pub fn alloc_from_free_list(&mut self, layout: Layout) -> Option<*mut u8> {
+        let level = Self::level_of(&layout);
+        let mut current_level = (level..BLOCK_LEVEL).find(|&i| !self.free_list[i].is_empty())?;
+        let block = self.free_list[current_level].pop().unwrap();
+        let region = self.region_of(block);
+        self.regions[region].toggle(current_level, block);
+        self.regions[region].free -= 1 << current_level;
+        // split, keeping the lower half
+        while current_level > level {
+            current_level -= 1;
+            self.push_free(region, current_level, block + (1 << current_level));
+        }
+        self.user += layout.size();
+        self.allocated += 1 << level;
+        Some(block as *mut u8)
+    }
+
+    fn level_of(layout: &Layout) -> usize {
         // align the size
         let size = max(
             layout.size().next_power_of_two(),
             max(layout.align(), UNIT_SIZE)
         );
-        let level = size.trailing_zeros() as usize; // log
-        for i in level..BLOCK_LEVEL {
-            if !self.free_list[i].is_empty() {
-                self.split(i, level);
-                self.user += layout.size();
-                self.allocated += size;
-                return self.free_list[level].pop().unwrap() as *mut u8;
-            }
-        }
-        panic!("[kernel] (Buddy Allocator) Heap memory run out!");
+        size.trailing_zeros() as usize // log
     }
     
-    fn merge(&mut self, from: usize, ptr: *mut u8) {
+    pub fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        let level = Self::level_of(&layout);
+        let region = self.region_of(ptr as usize);
         let mut current_ptr = ptr as usize;
-        let mut current_level = from;
-        while current_level < BLOCK_LEVEL {
+        let mut current_level = level;
+        // merge while the buddy is free too
+        while current_level + 1 < BLOCK_LEVEL
+            && self.regions[region].buddy_free(current_level, current_ptr)
+        {
             let buddy = current_ptr ^ (1 << current_level);
-            let mut flag = false;
-            for block in self.free_list[current_level].iter_mut() {
-                if block.value() as usize == buddy {
-                    block.pop();
-                    flag = true;
-                    break;
-                }
-            }
-            if flag {
-                self.free_list[current_level].pop();
-                current_ptr = min(current_ptr, buddy);
-                current_level += 1;
-                unsafe { self.free_list[current_level].push(current_ptr as *mut usize) };
-            } else {
-                break;
-            }
+            self.remove_free(region, current_level, buddy);
+            current_ptr = min(current_ptr, buddy);
+            current_level += 1;
         }
-    }
-    
-    pub fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
-        let size = max(
-            layout.size().next_power_of_two(),
-            max(layout.align(), UNIT_SIZE)
-        );
-        let level = size.trailing_zeros() as usize;
-        unsafe { self.free_list[level].push(ptr as *mut usize); }
-        self.merge(level, ptr);
+        self.push_free(region, current_level, current_ptr);
         self.user -= layout.size();
-        self.allocated -= size;
+        self.allocated -= 1 << level;
     }
 }
 
-pub struct LockedBuddyAllocator(Mutex<BuddyAllocator>);
+/// Called with the heap locked when it can not satisfy `layout`,
+/// it may add more space to the heap before the allocation is retried.
+pub type Rescue = fn(&mut BuddyAllocator, &Layout);
+
+/// Small objects come from the slab caches, which take their slabs from the buddy heap
+/// like bigger allocations do.
+pub struct LockedBuddyAllocator {
+    inner: Mutex<BuddyAllocator>,
+    slab: Mutex<SlabAllocator>,
+    rescue: Option<Rescue>,
+}
 
 impl LockedBuddyAllocator {
     pub const fn empty() -> Self {
-        Self(Mutex::new(BuddyAllocator::empty()))
+        Self {
+            inner: Mutex::new(BuddyAllocator::empty()),
+            slab: Mutex::new(SlabAllocator::new()),
+            rescue: None,
+        }
+    }
+
+    /// An empty heap that calls `rescue` to grow when it runs out.
+    pub const fn with_rescue(rescue: Rescue) -> Self {
+        Self {
+            inner: Mutex::new(BuddyAllocator::empty()),
+            slab: Mutex::new(SlabAllocator::new()),
+            rescue: Some(rescue),
+        }
     }
 
     pub fn lock(&self) -> MutexGuard<BuddyAllocator> {
-        self.0.lock()
+        self.inner.lock()
+    }
+
+    /// Add [start, end) to the heap while it is in use, like `BuddyAllocator::add_to_heap`.
+    ///
+    /// # Safety
+    ///
+    /// The range must be valid memory owned by nothing else.
+    pub unsafe fn add_to_heap(&self, start: usize, end: usize) -> bool {
+        unsafe { self.inner.lock().add_to_heap(start, end) }
+    }
+
+    /// Like `BuddyAllocator::remove_free_region`.
+    pub fn remove_free_region(&self, keep: usize) -> Option<(usize, usize)> {
+        self.inner.lock().remove_free_region(keep)
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        self.inner.lock().stats()
+    }
+
+    /// Usage of each slab cache, from the smallest objects up.
+    pub fn slab_stats(&self) -> [CacheStats; CACHE_COUNT] {
+        self.slab.lock().stats()
+    }
+
+    fn alloc_block(&self, layout: Layout) -> Option<*mut u8> {
+        let mut inner = self.inner.lock();
+        if let Some(ptr) = inner.alloc_from_free_list(layout) {
+            return Some(ptr);
+        }
+        if let Some(rescue) = self.rescue {
+            rescue(&mut inner, &layout);
+        }
+        inner.alloc_from_free_list(layout)
     }
 }
 
 unsafe impl GlobalAlloc for LockedBuddyAllocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        self.0.lock().alloc(layout)
+        match SlabAllocator::class_of(&layout) {
+            Some(class) => self
+                .slab
+                .lock()
+                .alloc(class, &layout, |slab_layout| self.alloc_block(slab_layout)),
+            None => self.alloc_block(layout),
+        }
+        // the allocation error handler takes over
+        .unwrap_or(null_mut())
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        self.0.lock().dealloc(ptr, layout)
+        match SlabAllocator::class_of(&layout) {
+            Some(class) => self.slab.lock().dealloc(class, ptr, &layout, |slab, slab_layout| {
+                self.inner.lock().dealloc(slab, slab_layout)
+            }),
+            None => self.inner.lock().dealloc(ptr, layout),
+        }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,220 @@
+use core::alloc::Layout;
+use core::ptr::null_mut;
+use crate::linked_list::LinkedList;
+
+/// Object sizes served by the caches, the ones in between powers of two
+/// waste less than rounding up to the next power of two.
+const SIZE_CLASSES: [usize; 16] = [
+    8, 16, 24, 32, 48, 64, 96, 128, 192, 256, 384, 512, 768, 1024, 1536, 2048,
+];
+pub const CACHE_COUNT: usize = SIZE_CLASSES.len();
+
+/// Objects per slab, the header takes the first few of them
+const SLAB_OBJECTS: usize = 16;
+const MIN_SLAB_SIZE: usize = 4096;
+
+/// Header at the start of each slab, a slab is a buddy block aligned to its size
+/// so the slab of an object is found by masking its address.
+struct Slab {
+    free: LinkedList,
+    in_use: usize,
+    prev: *mut Slab,
+    next: *mut Slab,
+}
+
+#[derive(Clone, Copy)]
+struct Cache {
+    size: usize,
+    slab_size: usize,
+    /// slabs with free objects
+    partial: *mut Slab,
+    slabs: usize,
+    objects: usize,
+    requested: usize,
+}
+
+/// Usage of a cache, sizes in bytes.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CacheStats {
+    pub object_size: usize,
+    pub slabs: usize,
+    pub slab_bytes: usize,
+    /// objects handed out
+    pub objects: usize,
+    /// bytes asked for by those objects
+    pub requested: usize,
+}
+
+impl CacheStats {
+    /// Bytes of handed out objects not asked for
+    pub fn internal_fragmentation(&self) -> usize {
+        self.objects * self.object_size - self.requested
+    }
+
+    /// Bytes of slabs not handed out, including the headers
+    pub fn external_fragmentation(&self) -> usize {
+        self.slab_bytes - self.objects * self.object_size
+    }
+
+    /// Requested bytes per thousand bytes of slabs
+    pub fn utilisation_permille(&self) -> usize {
+        if self.slab_bytes == 0 {
+            return 0;
+        }
+        self.requested * 1000 / self.slab_bytes
+    }
+}
+
+impl Cache {
+    const fn new(size: usize) -> Self {
+        let slab_size = (size * SLAB_OBJECTS).next_power_of_two();
+        Self {
+            size,
+            slab_size: if slab_size < MIN_SLAB_SIZE { MIN_SLAB_SIZE } else { slab_size },
+            partial: null_mut(),
+            slabs: 0,
+            objects: 0,
+            requested: 0,
+        }
+    }
+
+    fn slab_layout(&self) -> Layout {
+        Layout::from_size_align(self.slab_size, self.slab_size).unwrap()
+    }
+
+    fn link(&mut self, slab: *mut Slab) {
+        unsafe {
+            (*slab).prev = null_mut();
+            (*slab).next = self.partial;
+            if !self.partial.is_null() {
+                (*self.partial).prev = slab;
+            }
+        }
+        self.partial = slab;
+    }
+
+    fn unlink(&mut self, slab: *mut Slab) {
+        unsafe {
+            let (prev, next) = ((*slab).prev, (*slab).next);
+            if prev.is_null() {
+                self.partial = next;
+            } else {
+                (*prev).next = next;
+            }
+            if !next.is_null() {
+                (*next).prev = prev;
+            }
+        }
+    }
+
+    /// Carve a new slab out of `block`.
+    fn add_slab(&mut self, block: *mut u8) {
+        let slab = block as *mut Slab;
+        let first = size_of::<Slab>().div_ceil(self.size);
+        unsafe {
+            slab.write(Slab {
+                free: LinkedList::new(),
+                in_use: 0,
+                prev: null_mut(),
+                next: null_mut(),
+            });
+            for i in (first..self.slab_size / self.size).rev() {
+                (*slab).free.push(block.add(i * self.size) as *mut usize);
+            }
+        }
+        self.slabs += 1;
+        self.link(slab);
+    }
+}
+
+/// Caches of small objects carved out of slabs taken from the buddy allocator.
+pub struct SlabAllocator {
+    caches: [Cache; CACHE_COUNT],
+}
+
+unsafe impl Send for SlabAllocator {}
+
+impl SlabAllocator {
+    pub const fn new() -> Self {
+        let mut caches = [Cache::new(SIZE_CLASSES[0]); CACHE_COUNT];
+        let mut i = 1;
+        while i < CACHE_COUNT {
+            caches[i] = Cache::new(SIZE_CLASSES[i]);
+            i += 1;
+        }
+        Self { caches }
+    }
+
+    /// The cache serving `layout`, None if it is too big for any of them.
+    /// An object is aligned to the lowest set bit of its size.
+    pub fn class_of(layout: &Layout) -> Option<usize> {
+        SIZE_CLASSES
+            .iter()
+            .position(|&size| size >= layout.size() && 1 << size.trailing_zeros() >= layout.align())
+    }
+
+    /// Take an object from cache `class`, `alloc_slab` gives a new slab when all are full.
+    pub fn alloc(
+        &mut self,
+        class: usize,
+        layout: &Layout,
+        alloc_slab: impl FnOnce(Layout) -> Option<*mut u8>,
+    ) -> Option<*mut u8> {
+        let cache = &mut self.caches[class];
+        if cache.partial.is_null() {
+            cache.add_slab(alloc_slab(cache.slab_layout())?);
+        }
+        let slab = cache.partial;
+        let object = unsafe {
+            let object = (*slab).free.pop().unwrap();
+            (*slab).in_use += 1;
+            if (*slab).free.is_empty() {
+                cache.unlink(slab);
+            }
+            object
+        };
+        cache.objects += 1;
+        cache.requested += layout.size();
+        Some(object as *mut u8)
+    }
+
+    /// Return an object to cache `class`, `dealloc_slab` takes back a slab left empty.
+    pub fn dealloc(
+        &mut self,
+        class: usize,
+        ptr: *mut u8,
+        layout: &Layout,
+        dealloc_slab: impl FnOnce(*mut u8, Layout),
+    ) {
+        let cache = &mut self.caches[class];
+        let slab = (ptr as usize & !(cache.slab_size - 1)) as *mut Slab;
+        unsafe {
+            if (*slab).free.is_empty() {
+                cache.link(slab);
+            }
+            (*slab).free.push(ptr as *mut usize);
+            (*slab).in_use -= 1;
+            if (*slab).in_use == 0 {
+                cache.unlink(slab);
+                cache.slabs -= 1;
+                dealloc_slab(slab as *mut u8, cache.slab_layout());
+            }
+        }
+        cache.objects -= 1;
+        cache.requested -= layout.size();
+    }
+
+    pub fn stats(&self) -> [CacheStats; CACHE_COUNT] {
+        let mut stats = [CacheStats::default(); CACHE_COUNT];
+        for (stat, cache) in stats.iter_mut().zip(self.caches.iter()) {
+            *stat = CacheStats {
+                object_size: cache.size,
+                slabs: cache.slabs,
+                slab_bytes: cache.slabs * cache.slab_size,
+                objects: cache.objects,
+                requested: cache.requested,
+            };
+        }
+        stats
+    }
+}
@@ -0,0 +1,248 @@
+use buddy_allocator::{BuddyAllocator, HeapStats, LockedBuddyAllocator};
+use std::alloc::{alloc, dealloc, GlobalAlloc, Layout};
+use std::collections::BTreeMap;
+
+const HEAP_SIZE: usize = 1 << 20;
+
+/// Memory for a heap aligned to its size, freed when dropped.
+struct Space {
+    start: usize,
+    layout: Layout,
+}
+
+impl Space {
+    fn new(size: usize) -> Self {
+        let layout = Layout::from_size_align(size, size).unwrap();
+        Self { start: unsafe { alloc(layout) } as usize, layout }
+    }
+
+    fn end(&self) -> usize {
+        self.start + self.layout.size()
+    }
+}
+
+impl Drop for Space {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.start as *mut u8, self.layout) };
+    }
+}
+
+/// xorshift, so the sequences are the same on every run
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 as usize
+    }
+}
+
+fn heap(space: &Space) -> BuddyAllocator {
+    let mut heap = BuddyAllocator::empty();
+    unsafe { heap.add_to_heap(space.start, space.end()) };
+    heap
+}
+
+fn free_bytes(stats: &HeapStats) -> usize {
+    stats.free_blocks.iter().enumerate().map(|(level, count)| count << level).sum()
+}
+
+fn block_size(layout: &Layout) -> usize {
+    layout.size().next_power_of_two().max(layout.align()).max(2 * size_of::<usize>())
+}
+
+#[test]
+fn split_and_merge_back() {
+    let space = Space::new(HEAP_SIZE);
+    let mut heap = heap(&space);
+    let initial = heap.stats();
+    assert_eq!(free_bytes(&initial), initial.total);
+
+    let layout = Layout::from_size_align(4096, 8).unwrap();
+    let ptr = heap.alloc(layout);
+    assert!(!ptr.is_null());
+    let stats = heap.stats();
+    // the smallest block big enough was split down, leaving a free half on each level
+    let from = (12..).find(|&level| initial.free_blocks[level] > 0).unwrap();
+    assert!(from > 12);
+    for level in 12..from {
+        assert_eq!(stats.free_blocks[level], initial.free_blocks[level] + 1);
+    }
+    assert_eq!(stats.free_blocks[from], initial.free_blocks[from] - 1);
+    assert_eq!(stats.allocated, 4096);
+    assert_eq!(free_bytes(&stats) + stats.allocated, stats.total);
+
+    heap.dealloc(ptr, layout);
+    assert_eq!(heap.stats().free_blocks, initial.free_blocks);
+}
+
+#[test]
+fn buddies_merge_in_any_order() {
+    let space = Space::new(HEAP_SIZE);
+    let mut heap = heap(&space);
+    let initial = heap.stats();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let ptrs: Vec<_> = (0..256).map(|_| heap.alloc(layout)).collect();
+    // every other block first, none of them can merge yet
+    for ptr in ptrs.iter().step_by(2) {
+        heap.dealloc(*ptr, layout);
+    }
+    assert_eq!(heap.stats().free_blocks[6], initial.free_blocks[6] + 128);
+    for ptr in ptrs.iter().skip(1).step_by(2) {
+        heap.dealloc(*ptr, layout);
+    }
+    assert_eq!(heap.stats().free_blocks, initial.free_blocks);
+}
+
+#[test]
+fn blocks_are_aligned() {
+    let space = Space::new(HEAP_SIZE);
+    let mut heap = heap(&space);
+    let mut rng = Rng(1);
+    for _ in 0..1000 {
+        let align = 1 << (rng.next() % 13);
+        let layout = Layout::from_size_align(1 + rng.next() % 5000, align).unwrap();
+        let ptr = heap.alloc(layout) as usize;
+        assert_ne!(ptr, 0);
+        assert_eq!(ptr % align, 0);
+        // a block is aligned to its size
+        assert_eq!(ptr % block_size(&layout), 0);
+        heap.dealloc(ptr as *mut u8, layout);
+    }
+}
+
+#[test]
+fn running_out_returns_null() {
+    let space = Space::new(HEAP_SIZE);
+    let mut heap = heap(&space);
+    assert!(heap.alloc(Layout::from_size_align(HEAP_SIZE, 8).unwrap()).is_null());
+    let layout = Layout::from_size_align(4096, 8).unwrap();
+    let mut ptrs = Vec::new();
+    loop {
+        let ptr = heap.alloc(layout);
+        if ptr.is_null() {
+            break;
+        }
+        ptrs.push(ptr);
+    }
+    assert!(ptrs.len() > HEAP_SIZE / 4096 - 4);
+    let initial_total = heap.stats().total;
+    for ptr in ptrs {
+        heap.dealloc(ptr, layout);
+    }
+    assert_eq!(heap.stats().allocated, 0);
+    assert_eq!(free_bytes(&heap.stats()), initial_total);
+}
+
+#[test]
+fn random_sequences_keep_stats_consistent() {
+    let space = Space::new(HEAP_SIZE);
+    let mut heap = heap(&space);
+    let initial = heap.stats();
+    let mut rng = Rng(0x2025);
+    // start of each block handed out, with its end and layout
+    let mut live: BTreeMap<usize, (usize, Layout)> = BTreeMap::new();
+    for _ in 0..20000 {
+        if rng.next().is_multiple_of(2) || live.is_empty() {
+            let layout = Layout::from_size_align(1 + rng.next() % 3000, 1 << (rng.next() % 8)).unwrap();
+            let ptr = heap.alloc(layout) as usize;
+            if ptr == 0 {
+                continue;
+            }
+            let end = ptr + block_size(&layout);
+            assert!(live.range(..end).next_back().is_none_or(|(_, (prev_end, _))| *prev_end <= ptr));
+            assert!(space.start <= ptr && end <= space.end());
+            live.insert(ptr, (end, layout));
+        } else {
+            let ptr = *live.keys().nth(rng.next() % live.len()).unwrap();
+            let (_, layout) = live.remove(&ptr).unwrap();
+            heap.dealloc(ptr as *mut u8, layout);
+        }
+        let stats = heap.stats();
+        assert_eq!(stats.user, live.values().map(|(_, layout)| layout.size()).sum::<usize>());
+        assert_eq!(stats.allocated, live.values().map(|(_, layout)| block_size(layout)).sum::<usize>());
+        assert_eq!(free_bytes(&stats) + stats.allocated, stats.total);
+    }
+    for (ptr, (_, layout)) in live {
+        heap.dealloc(ptr as *mut u8, layout);
+    }
+    assert_eq!(heap.stats().free_blocks, initial.free_blocks);
+}
+
+#[test]
+fn regions_are_removed_only_when_free() {
+    let first = Space::new(HEAP_SIZE);
+    let second = Space::new(HEAP_SIZE);
+    let mut heap = heap(&first);
+    let total = heap.stats().total;
+    unsafe { heap.add_to_heap(second.start, second.end()) };
+    let initial = heap.stats();
+    assert!(initial.total > total);
+
+    // the first range can not hold two of them after its bitmap
+    let layout = Layout::from_size_align(HEAP_SIZE / 2, 8).unwrap();
+    let ptrs = [heap.alloc(layout), heap.alloc(layout)];
+    assert!(ptrs.iter().all(|ptr| !ptr.is_null()));
+    assert_eq!(heap.remove_free_region(1), None);
+    for ptr in ptrs {
+        heap.dealloc(ptr, layout);
+    }
+    assert_eq!(heap.stats().free_blocks, initial.free_blocks);
+    assert_eq!(heap.remove_free_region(1), Some((second.start, second.end())));
+    assert_eq!(heap.stats().total, total);
+    assert_eq!(free_bytes(&heap.stats()), total);
+}
+
+#[test]
+fn small_objects_come_from_slabs() {
+    let space = Space::new(HEAP_SIZE);
+    let allocator = LockedBuddyAllocator::empty();
+    unsafe { allocator.add_to_heap(space.start, space.end()) };
+    let initial = allocator.stats();
+    let mut rng = Rng(7);
+    let mut live = Vec::new();
+    for step in 0..20000 {
+        if rng.next().is_multiple_of(2) || live.is_empty() {
+            let layout = Layout::from_size_align(1 + rng.next() % 2048, 1 << (rng.next() % 4)).unwrap();
+            let ptr = unsafe { allocator.alloc(layout) };
+            assert_eq!(ptr as usize % layout.align(), 0);
+            unsafe { ptr.write_bytes(step as u8, layout.size()) };
+            live.push((ptr, layout, step as u8));
+        } else {
+            let (ptr, layout, value) = live.swap_remove(rng.next() % live.len());
+            assert!((0..layout.size()).all(|i| unsafe { *ptr.add(i) } == value));
+            unsafe { allocator.dealloc(ptr, layout) };
+        }
+    }
+    let requested: usize = allocator.slab_stats().iter().map(|stats| stats.requested).sum();
+    assert_eq!(requested, live.iter().map(|(_, layout, _)| layout.size()).sum::<usize>());
+    for (ptr, layout, _) in live {
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+    // empty slabs go back to the buddy heap
+    assert!(allocator.slab_stats().iter().all(|stats| stats.slabs == 0 && stats.objects == 0));
+    assert_eq!(allocator.stats().free_blocks, initial.free_blocks);
+}
+
+#[test]
+fn adding_past_the_last_region_fails() {
+    let spaces: Vec<_> = (0..64).map(|_| Space::new(1 << 16)).collect();
+    let mut heap = BuddyAllocator::empty();
+    let added = spaces
+        .iter()
+        .take_while(|space| unsafe { heap.add_to_heap(space.start, space.end()) })
+        .count();
+    assert_eq!(heap.regions_left(), 0);
+    assert!(added < spaces.len());
+    let total = heap.stats().total;
+    let last = &spaces[spaces.len() - 1];
+    assert!(!unsafe { heap.add_to_heap(last.start, last.end()) });
+    assert_eq!(heap.stats().total, total);
+    // the heap still works
+    let layout = Layout::from_size_align(4096, 8).unwrap();
+    let ptr = heap.alloc(layout);
+    assert!(!ptr.is_null());
+    heap.dealloc(ptr, layout);
+}
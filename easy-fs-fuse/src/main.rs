@@ -53,7 +53,8 @@ fn easy_fs_pack() -> std::io::Result<()> {
             .write(true)
             .create(true)
             .open(format!("{}{}", target_path, "fs.img"))?;
-        f.set_len(16 * 2048 * 512).unwrap();
+        // 64MiB of swap follows the file system
+        f.set_len((16 + 64) * 2048 * 512).unwrap();
         f
     })));
     // 创建 EasyFileSystem
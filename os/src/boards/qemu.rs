@@ -12,9 +12,12 @@ pub const MMIO_VIRT_IO: &[(usize, usize)] = &[
 pub const MMIO_VIRT_UART: (usize, usize) = (0x1000_0000, 0x100);
 // for shutdown
 pub const MMIO_VIRT_TEST: (usize, usize) = (0x10_0000, 0x1000);
+// for inter-processor interrupts
+pub const MMIO_VIRT_MSIP: (usize, usize) = (MMIO_CLINT_BASE + MSIP_OFFSET, 0x1000);
 /* ------------------------------------------------- */
 // for timer
 pub const MMIO_CLINT_BASE: usize = 0x2000000;
+pub const MSIP_OFFSET: usize = 0x0;
 pub const MTIME_OFFSET: usize = 0xBFF8;
 pub const MTIMECMP_OFFSET: usize = 0x4000;
 
@@ -1,7 +1,17 @@
+/// initial size of a user stack
 pub const USER_STACK_SIZE: usize = 4096 * 2;
+/// a user stack grows on page faults up to this size
+pub const USER_STACK_LIMIT: usize = 0x10_0000;
 pub const KERNEL_STACK_SIZE: usize = 4096 * 2;
+/// stack of a hart before it runs tasks
+pub const BOOT_STACK_SIZE: usize = 4096 * 16;
 
+/// the static part of the kernel heap, it grows from frames beyond that
 pub const KERNEL_HEAP_SIZE: usize = 0x30_0000;
+/// the kernel heap grows by at least this size when it runs out
+pub const KERNEL_HEAP_GROW_SIZE: usize = 0x10_0000;
+/// the least it grows by when frames are too fragmented for that, so it does not run out of ranges
+pub const KERNEL_HEAP_MIN_GROW_SIZE: usize = 0x1_0000;
 
 /*
 #[cfg(feature = "board_k210")]
@@ -19,7 +29,32 @@ pub const UART_DIVISOR: u16 = 3;
 pub const PAGE_SIZE: usize = 4096;
 pub const PAGE_SIZE_BITS: usize = 12;
 
+/// Sv39 by default, Sv48 with the `sv48` feature
+#[cfg(not(feature = "sv48"))]
+pub const PAGING_LEVELS: usize = 3;
+#[cfg(feature = "sv48")]
+pub const PAGING_LEVELS: usize = 4;
+/// the MODE field of satp for the paging mode
+#[cfg(not(feature = "sv48"))]
+pub const SATP_MODE: usize = 8;
+#[cfg(feature = "sv48")]
+pub const SATP_MODE: usize = 9;
+pub const VA_WIDTH: usize = PAGE_SIZE_BITS + 9 * PAGING_LEVELS;
+
+/// the highest page, sign-extended from the top of the address space in either mode
 pub const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
 pub const TRAP_CONTEXT_BASE: usize = TRAMPOLINE - PAGE_SIZE;
 
+/// where anonymous mappings are placed when mmap does not specify an address
+pub const MMAP_BASE: usize = 0x10_0000_0000;
+/// where user stacks of threads are placed, away from the heap growing after the elf
+pub const USER_STACK_BASE: usize = 0x20_0000_0000;
+/// end of the lower half of the address space used by user programs
+pub const USER_SPACE_END: usize = 1 << (VA_WIDTH - 1);
+
+/// the swap area follows the 16 MiB easy-fs image on the block device
+pub const SWAP_START_BLOCK: usize = 16 * 2048;
+/// 64 MiB of swap
+pub const SWAP_PAGES: usize = 16384;
+
 pub const INIT_PROC: usize = 0;
\ No newline at end of file
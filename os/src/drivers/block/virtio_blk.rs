@@ -2,7 +2,7 @@ use alloc::vec::Vec;
 use lazy_static::lazy_static;
 use virtio_drivers::{Hal, VirtIOBlk, VirtIOHeader};
 use easy_fs::BlockDevice;
-use crate::mm::{frame_alloc, StepByOne, PhysPageNum, PhysAddr, PageTable, FrameTracker, frame_dealloc, kernel_token, VirtAddr};
+use crate::mm::{frame_alloc_contiguous, PhysPageNum, PhysAddr, PageTable, FrameTracker, kernel_token, VirtAddr};
 use crate::sync::UPSafeCell;
 
 const VIRTIO0: usize = 0x10001000;
@@ -44,26 +44,24 @@ pub struct VirtioHal;
 
 impl Hal for VirtioHal {
     fn dma_alloc(pages: usize) -> usize {
-        let mut ppn_base = PhysPageNum(0);
-        for i in 0..pages {
-            let frame = frame_alloc().unwrap();
-            if i == 0 {
-                ppn_base = frame.ppn;
-            }
-            assert_eq!(frame.ppn.0, ppn_base.0 + i);
-            QUEUE_FRAMES.exclusive_access().push(frame);
-        }
-        let pa: PhysAddr = ppn_base.into();
+        // the driver reports a DMA error for address 0
+        let Some(frames) = frame_alloc_contiguous(pages) else {
+            return 0;
+        };
+        let pa: PhysAddr = frames.ppn.into();
+        QUEUE_FRAMES.exclusive_access().push(frames);
         pa.0
     }
 
     fn dma_dealloc(pa: usize, pages: usize) -> i32 {
-        let pa = PhysAddr::from(pa);
-        let mut ppn_base: PhysPageNum = pa.into();
-        for _ in 0..pages {
-            frame_dealloc(ppn_base);
-            ppn_base.step();
-        }
+        let ppn: PhysPageNum = PhysAddr::from(pa).into();
+        let mut queue_frames = QUEUE_FRAMES.exclusive_access();
+        let Some(idx) = queue_frames.iter().position(|frames| frames.ppn == ppn) else {
+            return -1;
+        };
+        assert_eq!(queue_frames[idx].pages(), pages);
+        // the frames are freed as the tracker is dropped
+        queue_frames.swap_remove(idx);
         0
     }
     
@@ -1,12 +1,26 @@
      .section .text.entry
      .globl _start
  _start:
-     la sp, boot_stack_top
+     # every hart starts here, tp keeps its id
+     csrr tp, mhartid
+     # harts beyond CORE_NUM have no stack
+     li t0, {core_num}
+     bgeu tp, t0, 2f
+     # sp = boot_stack_lower_bound + (hartid + 1) * BOOT_STACK_SIZE
+     addi t0, tp, 1
+     li t1, {boot_stack_size}
+     mul t0, t0, t1
+     la sp, boot_stack_lower_bound
+     add sp, sp, t0
      call rust_boot
+2:
+     wfi
+     j 2b
 
      .section .bss.stack
      .globl boot_stack_lower_bound
 boot_stack_lower_bound:
-     .space 4096 * 16
+     # a stack for each of the CORE_NUM harts
+     .space {boot_stack_size} * {core_num}
      .globl boot_stack_top
 boot_stack_top:
@@ -77,6 +77,9 @@ impl File for OSInode {
         }
         total_write_size
     }
+    fn inode(&self) -> Option<Arc<Inode>> {
+        Some(Arc::clone(&self.inner.exclusive_access().inode))
+    }
 }
 
 lazy_static! {
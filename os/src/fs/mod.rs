@@ -2,6 +2,8 @@ mod inode;
 mod stdio;
 mod pipe;
 
+use alloc::sync::Arc;
+use easy_fs::Inode;
 use crate::mm::UserBuffer;
 
 pub trait File: Send + Sync {
@@ -9,6 +11,8 @@ pub trait File: Send + Sync {
     fn writable(&self) -> bool;
     fn read(&self, buf: UserBuffer) -> usize;
     fn write(&self, buf: UserBuffer) -> usize;
+    /// The inode behind the file if it can be mapped into memory
+    fn inode(&self) -> Option<Arc<Inode>> { None }
 }
 
 pub use inode::{OpenFlags, open_file, list_apps};
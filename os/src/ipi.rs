@@ -0,0 +1,137 @@
+// Inter-processor interrupts are machine software interrupts of the CLINT, which the
+// M-mode handler passes on as supervisor software interrupts. The kernel runs with
+// interrupts off, so a hart answers them when it traps from user space or when it
+// polls while it spins on a lock or waits for other harts.
+
+use alloc::collections::VecDeque;
+use core::hint::spin_loop;
+use core::ptr::write_volatile;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+use crate::config::{CORE_NUM, MMIO_CLINT_BASE, MSIP_OFFSET};
+use crate::task::hart_id;
+
+/// look for a task to run
+const RESCHEDULE: usize = 1 << 0;
+/// run the calls queued for this hart
+const CALL: usize = 1 << 1;
+/// a timer tick, set by the M-mode handler
+const TICK: usize = 1 << 2;
+
+/// every hart that is online
+pub const ALL_HARTS: usize = usize::MAX;
+
+/// A function run by other harts, the sender waits until all of them have run it.
+/// It lives on the sender's stack, so a hart answering it while it holds the heap
+/// lock never frees memory.
+struct Call {
+    f: *const (dyn Fn() + Sync),
+    remaining: AtomicUsize,
+}
+
+/// A call queued for a hart, valid until the hart counts itself off `remaining`.
+struct CallRef(*const Call);
+
+unsafe impl Send for CallRef {}
+
+/// harts that answer IPIs
+static ONLINE: AtomicUsize = AtomicUsize::new(0);
+/// harts waiting for a task to run
+static IDLE: AtomicUsize = AtomicUsize::new(0);
+/// messages each hart has not handled yet, ticks included
+static PENDING: [AtomicUsize; CORE_NUM] = [const { AtomicUsize::new(0) }; CORE_NUM];
+/// calls queued for each hart, a plain spinlock since its holders never wait for other harts
+static CALLS: [Mutex<VecDeque<CallRef>>; CORE_NUM] = [const { Mutex::new(VecDeque::new()) }; CORE_NUM];
+
+/// Let other harts send IPIs to this one.
+pub fn set_online() {
+    ONLINE.fetch_or(1 << hart_id(), Ordering::AcqRel);
+}
+
+fn send(hart: usize, message: usize) {
+    PENDING[hart].fetch_or(message, Ordering::Release);
+    unsafe {
+        write_volatile((MMIO_CLINT_BASE + MSIP_OFFSET + 4 * hart) as *mut u32, 1);
+    }
+}
+
+/// Where the M-mode handler sets the tick of `hart`.
+pub fn tick_addr(hart: usize) -> usize {
+    &PENDING[hart] as *const AtomicUsize as usize
+}
+
+/// Ask `hart` to look for a task to run.
+pub fn send_reschedule(hart: usize) {
+    send(hart, RESCHEDULE);
+}
+
+/// Wake an idle hart other than this one for a task that has become ready.
+pub fn kick_idle_hart() {
+    let idle = IDLE.load(Ordering::Acquire) & !(1 << hart_id());
+    if idle != 0 {
+        send_reschedule(idle.trailing_zeros() as usize);
+    }
+}
+
+/// Mark this hart as waiting for a task or not.
+pub fn set_idle(idle: bool) {
+    if idle {
+        IDLE.fetch_or(1 << hart_id(), Ordering::AcqRel);
+    } else {
+        IDLE.fetch_and(!(1 << hart_id()), Ordering::AcqRel);
+    }
+}
+
+/// Run `f` on each online hart in the mask `harts`, this one included, and return
+/// after all of them have run it. `f` runs wherever the hart polls, so it must not take locks.
+pub fn call_on(harts: usize, f: impl Fn() + Send + Sync + 'static) {
+    let this = 1 << hart_id();
+    let others = harts & ONLINE.load(Ordering::Acquire) & !this;
+    if harts & this != 0 {
+        f();
+    }
+    if others == 0 {
+        return;
+    }
+    let call = Call {
+        f: &f,
+        remaining: AtomicUsize::new(others.count_ones() as usize),
+    };
+    for hart in (0..CORE_NUM).filter(|hart| others & 1 << hart != 0) {
+        CALLS[hart].lock().push_back(CallRef(&call));
+        send(hart, CALL);
+    }
+    // the others may be waiting for this hart at the same time
+    while call.remaining.load(Ordering::Acquire) != 0 {
+        handle_calls();
+        spin_loop();
+    }
+}
+
+/// Run the calls other harts have queued for this one.
+pub fn handle_calls() {
+    let hart = hart_id();
+    if PENDING[hart].fetch_and(!CALL, Ordering::AcqRel) & CALL == 0 {
+        return;
+    }
+    // a call queued after the flag is cleared sets it again
+    loop {
+        let Some(CallRef(call)) = CALLS[hart].lock().pop_front() else {
+            break;
+        };
+        // the sender returns and the call is gone once the count reaches zero
+        let call = unsafe { &*call };
+        unsafe { (*call.f)() };
+        call.remaining.fetch_sub(1, Ordering::Release);
+    }
+}
+
+/// Whether another hart has asked this one to reschedule since the last time.
+pub fn take_reschedule() -> bool {
+    PENDING[hart_id()].fetch_and(!RESCHEDULE, Ordering::AcqRel) & RESCHEDULE != 0
+}
+
+/// Whether a timer tick has come since the last time.
+pub fn take_tick() -> bool {
+    PENDING[hart_id()].fetch_and(!TICK, Ordering::AcqRel) & TICK != 0
+}
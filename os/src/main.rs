@@ -22,13 +22,23 @@ mod timer;
 mod mm;
 mod fs;
 mod drivers;
+mod ipi;
 
 use riscv::register::{mstatus, mepc, pmpaddr0, pmpcfg0, satp};
 
 use core::arch::{asm, global_asm};
-global_asm!(include_str!("entry.asm"));
+use core::hint::spin_loop;
+use core::sync::atomic::{AtomicBool, Ordering};
+global_asm!(
+    include_str!("entry.asm"),
+    core_num = const config::CORE_NUM,
+    boot_stack_size = const config::BOOT_STACK_SIZE,
+);
 
-/// initialize SBI and enter S-mode from M-mode.
+/// set by the boot hart once the kernel is ready for the other harts
+static BOOTED: AtomicBool = AtomicBool::new(false);
+
+/// initialize SBI and enter S-mode from M-mode, on every hart.
 #[unsafe(no_mangle)]
 pub fn rust_boot() -> ! {
     unsafe { mstatus::set_mpp(mstatus::MPP::Supervisor) }; // set MPP to S-mode for privilege change
@@ -51,9 +61,19 @@ pub fn rust_boot() -> ! {
     )}
 }
 
-/// kernel entry
+/// kernel entry, hart 0 sets up the kernel while the others wait for it
 #[unsafe(no_mangle)]
 pub fn rust_main() -> ! {
+    if task::hart_id() != 0 {
+        while !BOOTED.load(Ordering::Acquire) {
+            spin_loop();
+        }
+        mm::init_other_hart();
+        trap::init();
+        println!("[kernel] Hart {} started.", task::hart_id());
+        task::run_tasks();
+        panic!("Unreachable in rust_main!");
+    }
     clear_bss();
     sbi::init_uart();
     println!("[kernel] Hello, world!");
@@ -62,6 +82,7 @@ pub fn rust_main() -> ! {
     println!("after initproc!");
     trap::init();
     fs::list_apps();
+    BOOTED.store(true, Ordering::Release);
     task::run_tasks();
     panic!("Unreachable in rust_main!");
 }
@@ -2,10 +2,10 @@ use core::fmt::{Debug, Formatter, Result};
 use crate::config::*;
 use crate::mm::page_table::PageTableEntry;
 
-const PA_WIDTH_SV39: usize = 56;
-const VA_WIDTH_SV39: usize = 39;
-const PPN_WIDTH_SV39: usize = PA_WIDTH_SV39 - PAGE_SIZE_BITS;
-const VPN_WIDTH_SV39: usize = VA_WIDTH_SV39 - PAGE_SIZE_BITS;
+/// the same in Sv39 and Sv48, the virtual address width follows the paging mode
+const PA_WIDTH: usize = 56;
+const PPN_WIDTH: usize = PA_WIDTH - PAGE_SIZE_BITS;
+const VPN_WIDTH: usize = VA_WIDTH - PAGE_SIZE_BITS;
 
 #[derive(Clone, Copy, Ord, PartialOrd, Eq, PartialEq)]
 pub struct PhysAddr(pub usize);
@@ -20,19 +20,19 @@ pub struct PhysPageNum(pub usize);
 pub struct VirtPageNum(pub usize);
 
 impl From<usize> for PhysAddr {
-    fn from(v: usize) -> Self { Self(v & ((1 << PA_WIDTH_SV39) - 1)) }
+    fn from(v: usize) -> Self { Self(v & ((1 << PA_WIDTH) - 1)) }
 }
 
 impl From<usize> for PhysPageNum {
-    fn from(v: usize) -> Self { Self(v & ((1 << PPN_WIDTH_SV39) - 1)) }
+    fn from(v: usize) -> Self { Self(v & ((1 << PPN_WIDTH) - 1)) }
 }
 
 impl From<usize> for VirtAddr {
-    fn from(v: usize) -> Self { Self(v & ((1 << VA_WIDTH_SV39) - 1)) }
+    fn from(v: usize) -> Self { Self(v & ((1 << VA_WIDTH) - 1)) }
 }
 
 impl From<usize> for VirtPageNum {
-    fn from(v: usize) -> Self { Self(v & ((1 << VPN_WIDTH_SV39) - 1)) }
+    fn from(v: usize) -> Self { Self(v & ((1 << VPN_WIDTH) - 1)) }
 }
 
 impl From<PhysAddr> for usize {
@@ -45,8 +45,8 @@ impl From<PhysPageNum> for usize {
 
 impl From<VirtAddr> for usize {
     fn from(v: VirtAddr) -> Self {
-        if v.0 >= (1 << (VA_WIDTH_SV39 - 1)) {
-            v.0 | (!(1 << VA_WIDTH_SV39) - 1)
+        if v.0 >= (1 << (VA_WIDTH - 1)) {
+            v.0 | !((1 << VA_WIDTH) - 1)
         } else {
             v.0
         }
@@ -127,10 +127,10 @@ impl PhysPageNum {
 }
 
 impl VirtPageNum {
-    pub fn indexes(&self) -> [usize; 3] {
+    pub fn indexes(&self) -> [usize; PAGING_LEVELS] {
         let mut vpn = self.0;
-        let mut idx = [0usize; 3];
-        for i in (0..3).rev() {
+        let mut idx = [0usize; PAGING_LEVELS];
+        for i in (0..PAGING_LEVELS).rev() {
             idx[i] = vpn & 511;
             vpn >>= 9;
         }
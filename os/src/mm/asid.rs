@@ -0,0 +1,153 @@
+use alloc::vec::Vec;
+use core::arch::asm;
+use core::cell::Cell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::*;
+use riscv::register::satp;
+use crate::config::CORE_NUM;
+use crate::ipi::{call_on, ALL_HARTS};
+use crate::sync::UPSafeCell;
+use crate::task::hart_id;
+
+/// the kernel space is always tagged with id 0
+pub const KERNEL_ASID: usize = 0;
+const ASID_MASK: usize = 0xffff;
+
+#[derive(Copy, Clone)]
+struct Asid {
+    generation: usize,
+    id: usize,
+}
+
+/// Ids are given out in generations, when they run out a new generation starts
+/// with the TLB flushed and the address spaces of older ones take new ids.
+struct AsidAllocator {
+    /// the largest id supported by the hardware, 0 if there are no ids
+    max_id: usize,
+    generation: usize,
+    current: usize,
+    recycled: Vec<usize>,
+}
+
+impl AsidAllocator {
+    fn alloc(&mut self) -> Asid {
+        // without ids every address space shares the kernel one and the TLB is flushed on each switch
+        if self.max_id == 0 {
+            return Asid { generation: self.generation, id: KERNEL_ASID };
+        }
+        let id = if let Some(id) = self.recycled.pop() {
+            // entries of the last owner may be left on any hart
+            call_on(ALL_HARTS, move || flush_asid(id));
+            id
+        } else if self.current <= self.max_id {
+            self.current += 1;
+            self.current - 1
+        } else {
+            self.generation += 1;
+            self.recycled.clear();
+            self.current = KERNEL_ASID + 2;
+            // harts running older spaces trap for it and take new ids before they return
+            call_on(ALL_HARTS, flush_all);
+            KERNEL_ASID + 1
+        };
+        Asid { generation: self.generation, id }
+    }
+
+    fn dealloc(&mut self, asid: Asid) {
+        // ids of older generations are given out again already
+        if asid.generation == self.generation && asid.id != KERNEL_ASID {
+            self.recycled.push(asid.id);
+        }
+    }
+}
+
+/// the generation each hart has flushed its TLB for
+static FLUSHED_GENERATION: [AtomicUsize; CORE_NUM] = [const { AtomicUsize::new(0) }; CORE_NUM];
+
+lazy_static! {
+    static ref ASID_ALLOCATOR: UPSafeCell<AsidAllocator> = unsafe {
+        UPSafeCell::new(AsidAllocator {
+            max_id: 0,
+            generation: 0,
+            current: KERNEL_ASID + 1,
+            recycled: Vec::new(),
+        })
+    };
+}
+
+/// The address space id of a user page table, it is released when dropped.
+pub struct AsidHandle(Cell<Asid>);
+
+impl AsidHandle {
+    pub fn new() -> Self {
+        Self(Cell::new(ASID_ALLOCATOR.exclusive_access().alloc()))
+    }
+
+    /// Take a new id if a new generation has started since the last one was taken.
+    pub fn id(&self) -> usize {
+        let mut allocator = ASID_ALLOCATOR.exclusive_access();
+        if self.0.get().generation != allocator.generation {
+            self.0.set(allocator.alloc());
+        }
+        // entries this hart cached under ids of an older generation may be left
+        // whatever it did since the rollover, so they go before it runs the new id
+        if FLUSHED_GENERATION[hart_id()].swap(allocator.generation, Ordering::Relaxed) != allocator.generation {
+            flush_all();
+        }
+        self.0.get().id
+    }
+
+    /// Flush the entry of `va` on this hart, an id of an old generation has no
+    /// entries left and flushing it does no harm.
+    pub fn flush_page(&self, va: usize) {
+        flush_page(va, self.0.get().id);
+    }
+
+    /// Flush the entry of `va` on this hart and the other harts of `harts`.
+    pub fn shootdown_page(&self, va: usize, harts: usize) {
+        let id = self.0.get().id;
+        call_on(harts | 1 << hart_id(), move || flush_page(va, id));
+    }
+}
+
+impl Drop for AsidHandle {
+    fn drop(&mut self) {
+        ASID_ALLOCATOR.exclusive_access().dealloc(self.0.get());
+    }
+}
+
+/// Flush the entry of `va` in the kernel space on every hart,
+/// kernel stacks are mapped again at the same addresses.
+pub fn flush_kernel_page(va: usize) {
+    call_on(ALL_HARTS, move || flush_page(va, KERNEL_ASID));
+}
+
+pub fn flush_page(va: usize, id: usize) {
+    unsafe {
+        asm!("sfence.vma {}, {}", in(reg) va, in(reg) id);
+    }
+}
+
+fn flush_asid(id: usize) {
+    unsafe {
+        asm!("sfence.vma zero, {}", in(reg) id);
+    }
+}
+
+fn flush_all() {
+    unsafe {
+        asm!("sfence.vma");
+    }
+}
+
+/// Find out how many ids the hardware supports, the bits it does not support
+/// read back as zero. Paging has to be on.
+pub fn init() {
+    let token = satp::read().bits();
+    satp::write(token | ASID_MASK << 44);
+    let max_id = satp::read().bits() >> 44 & ASID_MASK;
+    satp::write(token);
+    flush_all();
+    ASID_ALLOCATOR.exclusive_access().max_id = max_id;
+    println!("[kernel] {} address space ids", max_id + 1);
+}
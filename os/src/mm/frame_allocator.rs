@@ -1,91 +1,156 @@
+use alloc::vec;
 use alloc::vec::Vec;
+use buddy_allocator::BuddyAllocator;
+use core::alloc::Layout;
 use lazy_static::*;
+use crate::sync::SpinLock;
 use crate::config::*;
 use crate::mm::address::*;
-use crate::sync::UPSafeCell;
+use crate::mm::heap_allocator::shrink_heap;
+use crate::task::reclaim_frame;
 
 trait FrameAllocator {
     fn new() -> Self;
-    fn alloc(&mut self) -> Option<PhysPageNum>;
-    fn dealloc(&mut self, ppn: PhysPageNum);
+    fn alloc_contiguous(&mut self, count: usize) -> Option<PhysPageNum>;
+    fn dealloc_contiguous(&mut self, ppn: PhysPageNum, count: usize);
 }
 
-pub struct StackFrameAllocator {
-    current: usize,
-    end: usize,
-    recycled: Vec<usize>,
+/// Frames are handed out in blocks of a power of two pages aligned to their size,
+/// the free lists are kept inside the free frames themselves.
+pub struct BuddyFrameAllocator {
+    frames: BuddyAllocator,
+    /// the first frame managed
+    base: usize,
+    /// a bit per frame set while it is allocated, to catch double frees in constant time
+    allocated: Vec<u64>,
+    total: usize,
+    free: usize,
 }
 
-impl FrameAllocator for StackFrameAllocator {
+/// Frame usage reported by `frame_stats`
+#[derive(Copy, Clone, Debug)]
+pub struct FrameStats {
+    pub total: usize,
+    pub free: usize,
+    pub used: usize,
+}
+
+impl BuddyFrameAllocator {
+    fn layout(count: usize) -> Layout {
+        Layout::from_size_align(count * PAGE_SIZE, PAGE_SIZE).unwrap()
+    }
+
+    fn is_allocated(&self, ppn: usize) -> bool {
+        let idx = ppn - self.base;
+        self.allocated[idx / 64] & 1 << (idx % 64) != 0
+    }
+
+    fn toggle(&mut self, ppn: usize) {
+        let idx = ppn - self.base;
+        self.allocated[idx / 64] ^= 1 << (idx % 64);
+    }
+}
+
+impl FrameAllocator for BuddyFrameAllocator {
     fn new() -> Self {
         Self {
-            current: 0,
-            end: 0,
-            recycled: Vec::new(),
+            frames: BuddyAllocator::empty(),
+            base: 0,
+            allocated: Vec::new(),
+            total: 0,
+            free: 0,
         }
     }
 
-    fn alloc(&mut self) -> Option<PhysPageNum> {
-        if let Some(ppn) = self.recycled.pop() {
-            Some(ppn.into())
-        } else {
-            if self.current == self.end {
-                None
-            } else {
-                self.current += 1;
-                Some((self.current - 1).into())
-            }
+    fn alloc_contiguous(&mut self, count: usize) -> Option<PhysPageNum> {
+        let pa = self.frames.alloc_from_free_list(Self::layout(count))?;
+        let ppn: PhysPageNum = PhysAddr::from(pa as usize).into();
+        let pages = count.next_power_of_two();
+        for i in ppn.0..ppn.0 + pages {
+            assert!(!self.is_allocated(i), "Frame ppn={:#x} is allocated twice!", i);
+            self.toggle(i);
         }
+        self.free -= pages;
+        Some(ppn)
     }
 
-    fn dealloc(&mut self, ppn: PhysPageNum) {
-        let ppn = ppn.0;
+    fn dealloc_contiguous(&mut self, ppn: PhysPageNum, count: usize) {
+        let pages = count.next_power_of_two();
         // validity check
-        if ppn >= self.current || self.recycled
-            .iter()
-            .find(|&v| {*v == ppn})
-            .is_some() {
-            panic!("Frame ppn={:#x} has not been allocated!", ppn);
+        for i in ppn.0..ppn.0 + pages {
+            if i < self.base || i - self.base >= self.allocated.len() * 64 || !self.is_allocated(i) {
+                panic!("Frame ppn={:#x} has not been allocated!", i);
+            }
+            self.toggle(i);
         }
-        // recycle
-        self.recycled.push(ppn);
+        let pa: PhysAddr = ppn.into();
+        self.frames.dealloc(pa.0 as *mut u8, Self::layout(count));
+        self.free += pages;
     }
 }
 
-impl StackFrameAllocator {
+impl BuddyFrameAllocator {
     pub fn init(&mut self, l: PhysPageNum, r: PhysPageNum) {
-        self.current = l.0;
-        self.end = r.0;
+        let start: PhysAddr = l.into();
+        let end: PhysAddr = r.into();
+        // the frames are free memory not used by anything else
+        unsafe { self.frames.add_to_heap(start.0, end.0) };
+        self.base = l.0;
+        // the buddy allocator keeps its bitmap in the first frames
+        self.total = self.frames.stats().total / PAGE_SIZE;
+        self.free = self.total;
+        self.allocated = vec![0; (r.0 - l.0).div_ceil(64)];
+    }
+
+    pub fn free_count(&self) -> usize {
+        self.free
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        FrameStats {
+            total: self.total,
+            free: self.free,
+            used: self.total - self.free,
+        }
     }
 }
 
+/// Owns `pages` frames from `ppn`, which are allocated together and freed together.
 pub struct FrameTracker {
     pub ppn: PhysPageNum,
+    pages: usize,
 }
 
 impl FrameTracker {
     pub fn new(ppn: PhysPageNum) -> Self {
+        Self::new_contiguous(ppn, 1)
+    }
+
+    pub fn new_contiguous(ppn: PhysPageNum, pages: usize) -> Self {
         // page cleaning
-        let bytes_array = ppn.get_bytes_array();
-        for i in bytes_array {
-            *i = 0;
+        for i in 0..pages {
+            PhysPageNum(ppn.0 + i).get_bytes_array().fill(0);
         }
-        Self { ppn }
+        Self { ppn, pages }
+    }
+
+    pub fn pages(&self) -> usize {
+        self.pages
     }
 }
 
 impl Drop for FrameTracker {
     fn drop(&mut self) {
-        frame_dealloc(self.ppn);
+        FRAME_ALLOCATOR
+            .lock()
+            .dealloc_contiguous(self.ppn, self.pages);
     }
 }
 
-type FrameAllocatorImpl = StackFrameAllocator;
+type FrameAllocatorImpl = BuddyFrameAllocator;
 
 lazy_static! {
-    pub static ref FRAME_ALLOCATOR: UPSafeCell<FrameAllocatorImpl> = unsafe {
-        UPSafeCell::new(FrameAllocatorImpl::new())
-    };
+    pub static ref FRAME_ALLOCATOR: SpinLock<FrameAllocatorImpl> = SpinLock::new(FrameAllocatorImpl::new());
 }
 
 pub fn init_frame_allocator() {
@@ -93,19 +158,66 @@ pub fn init_frame_allocator() {
         fn ekernel();
     }
     FRAME_ALLOCATOR
-        .exclusive_access()
+        .lock()
         .init(PhysAddr::from(ekernel as usize).ceil(), PhysAddr::from(MEMORY_END).floor());
 }
 
+/// Take back the frames the kernel heap does not use, then swap out pages of any
+/// process, before giving up. Swapping out single pages hardly makes room for
+/// contiguous frames, so only single frames are reclaimed that way.
+fn alloc_or_reclaim(pages: usize) -> Option<PhysPageNum> {
+    loop {
+        let ppn = FRAME_ALLOCATOR.lock().alloc_contiguous(pages);
+        if ppn.is_some() {
+            return ppn;
+        }
+        if shrink_heap() == 0 && (pages > 1 || !reclaim_frame()) {
+            return None;
+        }
+    }
+}
+
 pub fn frame_alloc() -> Option<FrameTracker> {
+    alloc_or_reclaim(1).map(|ppn| FrameTracker::new(ppn))
+}
+
+/// Allocate `pages` contiguous frames aligned to `pages` rounded up to a power of two,
+/// for DMA and large buffers.
+pub fn frame_alloc_contiguous(pages: usize) -> Option<FrameTracker> {
+    alloc_or_reclaim(pages).map(|ppn| FrameTracker::new_contiguous(ppn, pages))
+}
+
+/// Allocate `count` contiguous frames aligned to `count` for a huge page,
+/// each of them is freed on its own.
+pub fn frame_alloc_huge(count: usize) -> Option<Vec<FrameTracker>> {
+    assert!(count.is_power_of_two());
+    let ppn = alloc_or_reclaim(count)?;
+    Some((0..count).map(|i| FrameTracker::new(PhysPageNum(ppn.0 + i))).collect())
+}
+
+/// Frames for the kernel heap, which can not keep trackers of them while it grows.
+/// They are not cleared and the heap is not shrunk to find them.
+pub fn frame_alloc_untracked(pages: usize) -> Option<PhysPageNum> {
+    FRAME_ALLOCATOR
+        .lock()
+        .alloc_contiguous(pages)
+}
+
+pub fn frame_dealloc_untracked(ppn: PhysPageNum, pages: usize) {
     FRAME_ALLOCATOR
-        .exclusive_access()
-        .alloc()
-        .map(|ppn| FrameTracker::new(ppn))
+        .lock()
+        .dealloc_contiguous(ppn, pages);
 }
 
-pub fn frame_dealloc(ppn: PhysPageNum) {
+/// Number of frames that can still be allocated
+pub fn frame_free_count() -> usize {
     FRAME_ALLOCATOR
-        .exclusive_access()
-        .dealloc(ppn);
-}
\ No newline at end of file
+        .lock()
+        .free_count()
+}
+
+pub fn frame_stats() -> FrameStats {
+    FRAME_ALLOCATOR
+        .lock()
+        .stats()
+}
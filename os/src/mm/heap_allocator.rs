@@ -1,10 +1,12 @@
 use core::alloc::Layout;
 use core::ptr::addr_of_mut;
-use buddy_allocator::LockedBuddyAllocator;
-use crate::config::KERNEL_HEAP_SIZE;
+use buddy_allocator::{BuddyAllocator, LockedBuddyAllocator};
+use crate::config::{KERNEL_HEAP_GROW_SIZE, KERNEL_HEAP_MIN_GROW_SIZE, KERNEL_HEAP_SIZE, PAGE_SIZE};
+use crate::mm::address::{PhysAddr, PhysPageNum};
+use crate::mm::frame_allocator::{frame_alloc_untracked, frame_dealloc_untracked};
 
 #[global_allocator]
-static HEAP_ALLOCATOR: LockedBuddyAllocator = LockedBuddyAllocator::empty();
+static HEAP_ALLOCATOR: LockedBuddyAllocator = LockedBuddyAllocator::with_rescue(grow_heap);
 
 static mut KERNEL_HEAP_SPACE: [u8; KERNEL_HEAP_SIZE] = [0; KERNEL_HEAP_SIZE];
 
@@ -16,7 +18,43 @@ pub fn init_heap() {
     }
 }
 
+/// Add contiguous frames to the heap, they are reached through the identity mapping
+/// of physical memory in the kernel space, so nothing has to be mapped.
+/// The allocation fails if no more ranges can be added.
+fn grow_heap(heap: &mut BuddyAllocator, layout: &Layout) {
+    if heap.regions_left() == 0 {
+        return;
+    }
+    // twice the block size leaves room for an aligned block and the bitmap of the range
+    let min_size = (layout.size().max(layout.align()).next_power_of_two() * 2).max(KERNEL_HEAP_MIN_GROW_SIZE);
+    // doubling the heap keeps the number of ranges small
+    let size = min_size.max(KERNEL_HEAP_GROW_SIZE).max(heap.stats().total);
+    for size in [size, min_size] {
+        let pages = size.div_ceil(PAGE_SIZE).next_power_of_two();
+        if let Some(ppn) = frame_alloc_untracked(pages) {
+            let start: PhysAddr = ppn.into();
+            if !unsafe { heap.add_to_heap(start.0, start.0 + pages * PAGE_SIZE) } {
+                frame_dealloc_untracked(ppn, pages);
+            }
+            return;
+        }
+    }
+}
+
+/// Give the ranges the heap has grown by and no longer uses back to the frame allocator,
+/// return the number of frames given back.
+pub fn shrink_heap() -> usize {
+    let mut pages = 0;
+    // the static part stays
+    while let Some((start, end)) = HEAP_ALLOCATOR.remove_free_region(1) {
+        let ppn: PhysPageNum = PhysAddr::from(start).into();
+        frame_dealloc_untracked(ppn, (end - start) / PAGE_SIZE);
+        pages += (end - start) / PAGE_SIZE;
+    }
+    pages
+}
+
 #[alloc_error_handler]
 pub fn handle_alloc_error(layout: Layout) -> ! {
     panic!("Heap allocation error: layout = {:?}", layout);
-}
\ No newline at end of file
+}
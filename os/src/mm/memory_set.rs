@@ -1,19 +1,43 @@
-use alloc::collections::BTreeMap;
+use alloc::collections::{BTreeMap, BTreeSet};
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::arch::asm;
 use lazy_static::*;
 use riscv::register::satp;
+use crate::sync::SpinLock;
+use easy_fs::Inode;
 use crate::config::*;
-use crate::sync::UPSafeCell;
+use crate::task::reclaim_frame;
 use super::address::{VirtAddr, VirtPageNum, VPNRange, PhysPageNum, StepByOne, PhysAddr};
-use super::frame_allocator::{frame_alloc, FrameTracker};
-use super::page_table::{PTEFlags, PageTable, PageTableEntry};
+use super::frame_allocator::{frame_alloc, frame_alloc_huge, frame_free_count, FrameTracker};
+use super::heap_allocator::shrink_heap;
+use super::shm::ShmSegment;
+use super::swap::{swap_alloc, SwapSlot};
+use super::page_table::{PTEFlags, PageSize, PageTable, PageTableEntry};
 
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub enum MapType {
     Identical,
     Framed,
+    /// frames are allocated on the first access
+    Lazy,
+    /// frames belong to a shared memory segment
+    Shared,
+    /// like `Lazy`, but in 2 MiB pages when contiguous frames are left
+    Huge,
+}
+
+/// How a user page fault is resolved
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum PageFault {
+    /// the page can be accessed now
+    Handled,
+    /// a real fault of the program
+    Invalid,
+    /// no frame is left for the page
+    OutOfMemory,
+    /// a stack would grow past its limit or into other areas
+    StackOverflow,
 }
 
 bitflags! {
@@ -25,11 +49,42 @@ bitflags! {
     } 
 }
 
+/// Where pages of a lazy area are filled from.
+#[derive(Clone)]
+pub enum MapBacking {
+    /// start-aligned `len` bytes of a program file from `offset`, the rest is zero-filled
+    Elf {
+        inode: Arc<Inode>,
+        offset: usize,
+        len: usize,
+    },
+    /// file content from `offset`, dirty pages are written back if `shared`
+    File {
+        inode: Arc<Inode>,
+        offset: usize,
+        shared: bool,
+    },
+    /// pages of a shared memory segment from `offset`
+    Shm {
+        segment: Arc<ShmSegment>,
+        offset: usize,
+    },
+}
+
 pub struct MapArea {
     vpn_range: VPNRange,
-    data_frames: BTreeMap<VirtPageNum, FrameTracker>,
+    data_frames: BTreeMap<VirtPageNum, Arc<FrameTracker>>,
     map_type: MapType,
     map_perm: MapPermission,
+    /// mprotect can not grant more than this
+    max_perm: MapPermission,
+    backing: Option<MapBacking>,
+    /// pages of a shared file mapping written since the last write-back
+    dirty: BTreeSet<VirtPageNum>,
+    /// pages in the swap area, a present page keeps its slot while it is clean
+    swapped: BTreeMap<VirtPageNum, Arc<SwapSlot>>,
+    /// a stack area grows down to here on page faults below it
+    stack_bottom: Option<VirtPageNum>,
 }
 
 impl MapArea {
@@ -46,6 +101,11 @@ impl MapArea {
             data_frames: BTreeMap::new(),
             map_type,
             map_perm,
+            max_perm: MapPermission::all(),
+            backing: None,
+            dirty: BTreeSet::new(),
+            swapped: BTreeMap::new(),
+            stack_bottom: None,
         }
     }
 
@@ -58,20 +118,72 @@ impl MapArea {
             data_frames: BTreeMap::new(),
             map_type: another.map_type,
             map_perm: another.map_perm,
+            max_perm: another.max_perm,
+            backing: another.backing.clone(),
+            dirty: BTreeSet::new(),
+            swapped: BTreeMap::new(),
+            stack_bottom: another.stack_bottom,
         }
     }
     
-    pub fn map(&mut self, page_table: &mut PageTable) {
+    /// Return None if out of memory, with nothing of the area left mapped.
+    pub fn map(&mut self, page_table: &mut PageTable) -> Option<()> {
+        // pages of a lazy area are mapped on the first access
+        if matches!(self.map_type, MapType::Lazy | MapType::Huge) {
+            return Some(());
+        }
+        if self.map_type == MapType::Identical {
+            let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
+            let pages = self.identical_pages();
+            for (i, (vpn, size)) in pages.iter().enumerate() {
+                if page_table.map_huge(*vpn, PhysPageNum(vpn.0), pte_flags, *size).is_none() {
+                    for (mapped_vpn, _) in pages[..i].iter() {
+                        page_table.unmap(*mapped_vpn);
+                    }
+                    return None;
+                }
+            }
+            return Some(());
+        }
         for vpn in self.vpn_range {
-            self.map_one(page_table, vpn);
+            if self.map_one(page_table, vpn).is_none() {
+                for mapped_vpn in VPNRange::new(self.vpn_range.get_start(), vpn) {
+                    self.unmap_one(page_table, mapped_vpn);
+                }
+                return None;
+            }
         }
+        Some(())
     }
     
     pub fn unmap(&mut self, page_table: &mut PageTable) {
+        if self.map_type == MapType::Identical {
+            for (vpn, _) in self.identical_pages() {
+                page_table.unmap(vpn);
+            }
+            return;
+        }
+        self.write_back(self.vpn_range.get_start(), self.vpn_range.get_end());
         for vpn in self.vpn_range {
             self.unmap_one(page_table, vpn);
         }
     }
+
+    /// Identity mappings use the largest pages alignment allows.
+    fn identical_pages(&self) -> Vec<(VirtPageNum, PageSize)> {
+        let end_vpn = self.vpn_range.get_end();
+        let mut pages = Vec::new();
+        let mut vpn = self.vpn_range.get_start();
+        while vpn < end_vpn {
+            let size = [PageSize::GigaPage, PageSize::MegaPage, PageSize::Page]
+                .into_iter()
+                .find(|size| vpn.0 % size.pages() == 0 && vpn.0 + size.pages() <= end_vpn.0)
+                .unwrap();
+            pages.push((vpn, size));
+            vpn = VirtPageNum(vpn.0 + size.pages());
+        }
+        pages
+    }
     
     /// data: start-aligned but maybe with shorter length
     /// assume that all frames were cleared before
@@ -96,30 +208,341 @@ impl MapArea {
         }
     }
     
-    pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
+    /// Return None if out of memory, with the page left unmapped.
+    pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> Option<()> {
         let ppn: PhysPageNum;
         match self.map_type {
             MapType::Identical => {
                 ppn = PhysPageNum(vpn.0);
             }
-            MapType::Framed => {
-                let frame = frame_alloc().unwrap();
+            MapType::Framed | MapType::Lazy | MapType::Huge => {
+                let frame = frame_alloc()?;
+                ppn = frame.ppn;
+                self.data_frames.insert(vpn, Arc::new(frame));
+            }
+            MapType::Shared => {
+                let Some(MapBacking::Shm { segment, offset }) = &self.backing else {
+                    unreachable!();
+                };
+                let page_idx = (vpn.0 - self.vpn_range.get_start().0) + offset / PAGE_SIZE;
+                let frame = segment.frame(page_idx);
                 ppn = frame.ppn;
                 self.data_frames.insert(vpn, frame);
             }
         }
         let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
-        page_table.map(vpn, ppn, pte_flags);
+        if page_table.map(vpn, ppn, pte_flags).is_none() {
+            self.data_frames.remove(&vpn);
+            return None;
+        }
+        Some(())
     }
     
     pub fn unmap_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
-        match self.map_type {
-            MapType::Framed => {
+        if self.map_type != MapType::Identical {
+            self.swapped.remove(&vpn);
+            // pages never accessed or swapped out are not in the page table
+            if self.data_frames.remove(&vpn).is_none() {
+                return;
+            }
+        }
+        match page_table.page_size(vpn) {
+            Some(size) if size != PageSize::Page => {
+                // the whole huge page goes with the frames of its other pages
+                let start_vpn = VirtPageNum(vpn.0 - vpn.0 % size.pages());
+                for page_vpn in VPNRange::new(start_vpn, VirtPageNum(start_vpn.0 + size.pages())) {
+                    self.data_frames.remove(&page_vpn);
+                }
+                page_table.unmap(start_vpn);
+            }
+            _ => page_table.unmap(vpn),
+        }
+    }
+
+    /// Change the permission of the area and its mapped pages.
+    pub fn set_permission(&mut self, page_table: &mut PageTable, map_perm: MapPermission) {
+        self.map_perm = map_perm;
+        for (vpn, frame) in self.data_frames.iter() {
+            // a huge page is remapped through its first page
+            if page_table.page_size(*vpn).is_some_and(|size| vpn.0 % size.pages() != 0) {
+                continue;
+            }
+            page_table.remap(*vpn, frame.ppn, self.page_flags(*vpn));
+        }
+    }
+
+    /// Flags of a present page, which is read-only until a store fault if it is
+    /// shared by fork or not dirty in a shared file mapping, unless it is shared memory.
+    fn page_flags(&self, vpn: VirtPageNum) -> PTEFlags {
+        let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
+        let writable = if self.map_type == MapType::Shared {
+            true
+        } else if self.is_shared() {
+            self.dirty.contains(&vpn)
+        } else {
+            Arc::strong_count(&self.data_frames[&vpn]) == 1
+        };
+        if writable { pte_flags } else { pte_flags - PTEFlags::W }
+    }
+
+    fn is_shared(&self) -> bool {
+        matches!(self.backing, Some(MapBacking::File { shared: true, .. }))
+    }
+
+    /// Move the end of the area to `new_end_vpn`, unmapping the pages cut off.
+    /// Return None if out of memory, with the area unchanged.
+    pub fn resize(&mut self, page_table: &mut PageTable, new_end_vpn: VirtPageNum) -> Option<()> {
+        let start_vpn = self.vpn_range.get_start();
+        let end_vpn = self.vpn_range.get_end();
+        if new_end_vpn < end_vpn {
+            for vpn in VPNRange::new(new_end_vpn, end_vpn) {
+                self.unmap_one(page_table, vpn);
+            }
+        } else if self.map_type != MapType::Lazy {
+            for vpn in VPNRange::new(end_vpn, new_end_vpn) {
+                if self.map_one(page_table, vpn).is_none() {
+                    for mapped_vpn in VPNRange::new(end_vpn, vpn) {
+                        self.unmap_one(page_table, mapped_vpn);
+                    }
+                    return None;
+                }
+            }
+        }
+        self.vpn_range = VPNRange::new(start_vpn, new_end_vpn);
+        Some(())
+    }
+
+    /// Split the area at `vpn`, keep [start, vpn) and return [vpn, end).
+    pub fn split_off(&mut self, vpn: VirtPageNum) -> MapArea {
+        let start_vpn = self.vpn_range.get_start();
+        let end_vpn = self.vpn_range.get_end();
+        assert!(start_vpn < vpn && vpn < end_vpn, "split {:?} out of area", vpn);
+        self.vpn_range = VPNRange::new(start_vpn, vpn);
+        let split_size = (vpn.0 - start_vpn.0) * PAGE_SIZE;
+        let backing = match &self.backing {
+            Some(MapBacking::Elf { inode, offset, len }) if split_size < *len => Some(MapBacking::Elf {
+                inode: Arc::clone(inode),
+                offset: offset + split_size,
+                len: len - split_size,
+            }),
+            Some(MapBacking::File { inode, offset, shared }) => Some(MapBacking::File {
+                inode: Arc::clone(inode),
+                offset: offset + split_size,
+                shared: *shared,
+            }),
+            Some(MapBacking::Shm { segment, offset }) => Some(MapBacking::Shm {
+                segment: Arc::clone(segment),
+                offset: offset + split_size,
+            }),
+            _ => None,
+        };
+        Self {
+            vpn_range: VPNRange::new(vpn, end_vpn),
+            data_frames: self.data_frames.split_off(&vpn),
+            map_type: self.map_type,
+            map_perm: self.map_perm,
+            max_perm: self.max_perm,
+            backing,
+            dirty: self.dirty.split_off(&vpn),
+            swapped: self.swapped.split_off(&vpn),
+            // the lower part keeps growing
+            stack_bottom: None,
+        }
+    }
+
+    fn contains(&self, vpn: VirtPageNum) -> bool {
+        self.vpn_range.get_start() <= vpn && vpn < self.vpn_range.get_end()
+    }
+
+    fn overlaps(&self, start_vpn: VirtPageNum, end_vpn: VirtPageNum) -> bool {
+        self.vpn_range.get_start() < end_vpn && start_vpn < self.vpn_range.get_end()
+    }
+
+    /// Allocate and fill a page of a lazy area on its first access or a swapped out page,
+    /// the fault is invalid if the page is neither. A page left zero is read from the zero
+    /// frame until it is written.
+    pub fn load_page(&mut self, page_table: &mut PageTable, vpn: VirtPageNum, is_store: bool) -> PageFault {
+        if self.data_frames.contains_key(&vpn)
+            || (!matches!(self.map_type, MapType::Lazy | MapType::Huge)
+                && !self.swapped.contains_key(&vpn))
+        {
+            return PageFault::Invalid;
+        }
+        if self.map_type == MapType::Huge && self.load_huge_page(page_table, vpn) {
+            return PageFault::Handled;
+        }
+        let swapped = self.swapped.get(&vpn);
+        let start = (vpn.0 - self.vpn_range.get_start().0) * PAGE_SIZE;
+        let zero = swapped.is_none()
+            && match &self.backing {
+                Some(MapBacking::Elf { len, .. }) => start >= *len,
+                Some(MapBacking::File { .. }) => false,
+                _ => true,
+            };
+        if zero && !is_store && self.map_type == MapType::Lazy {
+            self.data_frames.insert(vpn, ZERO_FRAME.clone());
+            // read-only while the zero frame is shared
+            if page_table.map(vpn, ZERO_FRAME.ppn, self.page_flags(vpn) | PTEFlags::A).is_none() {
                 self.data_frames.remove(&vpn);
+                return PageFault::OutOfMemory;
+            }
+            return PageFault::Handled;
+        }
+        let Some(frame) = frame_alloc() else {
+            return PageFault::OutOfMemory;
+        };
+        if let Some(slot) = swapped {
+            slot.read(frame.ppn);
+        } else {
+            match &self.backing {
+                Some(MapBacking::Elf { inode, offset, len }) if start < *len => {
+                    let size = (len - start).min(PAGE_SIZE);
+                    inode.read_at(offset + start, &mut frame.ppn.get_bytes_array()[..size]);
+                }
+                Some(MapBacking::File { inode, offset, .. }) => {
+                    // the part beyond the end of file stays zero
+                    inode.read_at(offset + start, frame.ppn.get_bytes_array());
+                }
+                _ => {}
             }
-            _ => {}
+        }
+        let ppn = frame.ppn;
+        self.data_frames.insert(vpn, Arc::new(frame));
+        // about to be accessed, keep it from being evicted right away
+        if page_table.map(vpn, ppn, self.page_flags(vpn) | PTEFlags::A).is_none() {
+            self.data_frames.remove(&vpn);
+            return PageFault::OutOfMemory;
+        }
+        PageFault::Handled
+    }
+
+    /// Map the 2 MiB page holding `vpn` if it lies in the area with none of its pages present,
+    /// return false if it can not be, and a 4 KiB page should be loaded instead.
+    fn load_huge_page(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> bool {
+        let pages = PageSize::MegaPage.pages();
+        let start_vpn = VirtPageNum(vpn.0 - vpn.0 % pages);
+        let end_vpn = VirtPageNum(start_vpn.0 + pages);
+        if start_vpn < self.vpn_range.get_start()
+            || end_vpn > self.vpn_range.get_end()
+            || self.data_frames.range(start_vpn..end_vpn).next().is_some()
+        {
+            return false;
+        }
+        let Some(frames) = frame_alloc_huge(pages) else {
+            return false;
+        };
+        let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap() | PTEFlags::A;
+        if page_table.map_huge(start_vpn, frames[0].ppn, pte_flags, PageSize::MegaPage).is_none() {
+            return false;
+        }
+        for (page_vpn, frame) in VPNRange::new(start_vpn, end_vpn).into_iter().zip(frames) {
+            self.data_frames.insert(page_vpn, Arc::new(frame));
+        }
+        true
+    }
+
+    /// Only resident private user pages are swapped out.
+    fn is_evictable(&self) -> bool {
+        matches!(self.map_type, MapType::Framed | MapType::Lazy)
+            && self.map_perm.contains(MapPermission::U)
+            && !self.is_shared()
+    }
+
+    /// Write the page to the swap area unless it has a clean copy there and unmap it,
+    /// return false if the swap area is full.
+    fn swap_out(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> bool {
+        let ppn = self.data_frames[&vpn].ppn;
+        let dirty = page_table.translate(vpn).unwrap().dirty();
+        if dirty || !self.swapped.contains_key(&vpn) {
+            // a slot still used by a forked process can not be overwritten
+            let slot = match self.swapped.remove(&vpn) {
+                Some(slot) if Arc::strong_count(&slot) == 1 => slot,
+                _ => match swap_alloc() {
+                    Some(slot) => Arc::new(slot),
+                    None => return false,
+                },
+            };
+            slot.write(ppn);
+            self.swapped.insert(vpn, slot);
         }
         page_table.unmap(vpn);
+        self.data_frames.remove(&vpn);
+        true
+    }
+
+    /// The kernel writes user pages through physical addresses without setting
+    /// the dirty bit, so the swap copy of a present page is dropped instead.
+    pub fn drop_swap_copy(&mut self, vpn: VirtPageNum) {
+        if self.data_frames.contains_key(&vpn) {
+            self.swapped.remove(&vpn);
+        }
+    }
+
+    /// Make the faulting page writable, marking it dirty in a shared file mapping
+    /// or giving it a private frame if it is shared by fork,
+    /// the fault is invalid if it is caused by neither.
+    pub fn handle_store_fault(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> PageFault {
+        if matches!(self.map_type, MapType::Identical | MapType::Shared | MapType::Huge)
+            || !self.map_perm.contains(MapPermission::W)
+        {
+            return PageFault::Invalid;
+        }
+        match page_table.translate(vpn) {
+            Some(pte) if pte.is_valid() && !pte.writable() => {}
+            _ => return PageFault::Invalid,
+        }
+        let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
+        let frame = self.data_frames.get(&vpn).unwrap();
+        if self.is_shared() {
+            // written back later instead of copied
+            self.dirty.insert(vpn);
+            page_table.remap(vpn, frame.ppn, pte_flags);
+            return PageFault::Handled;
+        }
+        if Arc::strong_count(frame) == 1 {
+            // all other sharers are gone, take the frame over
+            page_table.remap(vpn, frame.ppn, pte_flags);
+        } else {
+            let Some(new_frame) = frame_alloc() else {
+                return PageFault::OutOfMemory;
+            };
+            // new frames are zero already
+            if !Arc::ptr_eq(frame, &ZERO_FRAME) {
+                new_frame.ppn.get_bytes_array().copy_from_slice(frame.ppn.get_bytes_array());
+            }
+            page_table.remap(vpn, new_frame.ppn, pte_flags);
+            self.data_frames.insert(vpn, Arc::new(new_frame));
+        }
+        PageFault::Handled
+    }
+
+    /// Write dirty pages in [start_vpn, end_vpn) back to the file without extending it,
+    /// return the pages written.
+    pub fn write_back(&mut self, start_vpn: VirtPageNum, end_vpn: VirtPageNum) -> Vec<VirtPageNum> {
+        let Some(MapBacking::File { inode, offset, .. }) = &self.backing else {
+            return Vec::new();
+        };
+        let file_size = inode.size();
+        let mut written = Vec::new();
+        for vpn in self.dirty.range(start_vpn..end_vpn) {
+            let pos = offset + (vpn.0 - self.vpn_range.get_start().0) * PAGE_SIZE;
+            if pos < file_size {
+                let len = PAGE_SIZE.min(file_size - pos);
+                inode.write_at(pos, &self.data_frames[vpn].ppn.get_bytes_array()[..len]);
+            }
+            written.push(*vpn);
+        }
+        for vpn in written.iter() {
+            self.dirty.remove(vpn);
+        }
+        written
+    }
+}
+
+impl Drop for MapArea {
+    fn drop(&mut self) {
+        // shared file pages are written back when the process exits or execs
+        self.write_back(self.vpn_range.get_start(), self.vpn_range.get_end());
     }
 }
 
@@ -136,40 +559,89 @@ unsafe extern "C" {
     fn strampoline();
 }
 
+/// frames a page fault may take: the page and the levels of page tables below the root
+const FAULT_FRAMES: usize = PAGING_LEVELS;
+
 pub struct MemorySet {
     page_table: PageTable,
     areas: Vec<MapArea>,
+    /// where the clock algorithm looks for the next page to evict
+    clock_hand: VirtPageNum,
 }
 
 impl MemorySet {
-    pub fn new_bare() -> Self {
+    /// Return None if out of memory
+    pub fn new_bare() -> Option<Self> {
+        Some(Self::with_page_table(PageTable::new()?))
+    }
+
+    fn with_page_table(page_table: PageTable) -> Self {
         Self {
-            page_table: PageTable::new(),
+            page_table,
             areas: Vec::new(),
+            clock_hand: VirtPageNum(0),
         }
     }
 
-    fn push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) {
-        map_area.map(&mut self.page_table);
+    /// Return None if out of memory, with the area not added.
+    fn push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) -> Option<()> {
+        map_area.map(&mut self.page_table)?;
         if let Some(data) = data {
             map_area.copy_data(&self.page_table, data);
         }
         self.areas.push(map_area);
+        Some(())
     }
     
-    /// Assume that no conflicts
+    /// Assume that no conflicts, return None if out of memory
     pub fn insert_framed_area(
         &mut self,
         start_va: VirtAddr, end_va: VirtAddr, permission: MapPermission
-    ) {
+    ) -> Option<()> {
         self.push(MapArea::new(
             start_va,
             end_va,
             MapType::Framed,
             permission
-        ), None);
+        ), None)
     }
     
+    /// Assume that no conflicts, frames are allocated on the first access
+    pub fn insert_lazy_area(
+        &mut self,
+        start_va: VirtAddr, end_va: VirtAddr, permission: MapPermission
+    ) {
+        // nothing to map yet, so it can not fail
+        self.areas.push(MapArea::new(
+            start_va,
+            end_va,
+            MapType::Lazy,
+            permission
+        ));
+    }
+
+    /// Assume that no conflicts, the stack grows down to `bottom_va` on page faults.
+    pub fn insert_stack_area(
+        &mut self,
+        start_va: VirtAddr, end_va: VirtAddr, bottom_va: VirtAddr, permission: MapPermission
+    ) {
+        let mut map_area = MapArea::new(
+            start_va,
+            end_va,
+            MapType::Lazy,
+            permission
+        );
+        map_area.stack_bottom = Some(bottom_va.floor());
+        self.areas.push(map_area);
+    }
+
+    /// Like `remove_area_with_start_vpn`, for areas whose start moves such as stacks.
+    pub fn remove_area_containing(&mut self, vpn: VirtPageNum) {
+        if let Some(area) = self.areas.iter().find(|area| area.contains(vpn)) {
+            self.remove_area_with_start_vpn(area.vpn_range.get_start());
+        }
+    }
+
     pub fn remove_area_with_start_vpn(&mut self, start_vpn: VirtPageNum) {
         if let Some((idx, area)) = self
             .areas
@@ -182,14 +654,276 @@ impl MemorySet {
         }
     }
     
+    /// Map anonymous memory at [start, start + len), or at a free place if `start` is 0.
+    /// Return the start address, or None if it overlaps existing areas.
+    pub fn mmap(&mut self, start: usize, len: usize, permission: MapPermission) -> Option<usize> {
+        let start = self.check_mmap_range(start, len, PAGE_SIZE)?;
+        self.insert_lazy_area(start.into(), (start + len).into(), permission);
+        Some(start)
+    }
+
+    /// Map anonymous memory like `mmap` in 2 MiB pages, `start` must be aligned to them
+    /// and `len` is rounded up to them.
+    pub fn mmap_huge(&mut self, start: usize, len: usize, permission: MapPermission) -> Option<usize> {
+        let huge_size = PageSize::MegaPage.pages() * PAGE_SIZE;
+        if start % huge_size != 0 {
+            return None;
+        }
+        let len = len.next_multiple_of(huge_size);
+        let start = self.check_mmap_range(start, len, huge_size)?;
+        self.areas.push(MapArea::new(
+            start.into(),
+            (start + len).into(),
+            MapType::Huge,
+            permission,
+        ));
+        Some(start)
+    }
+
+    /// Map `inode` from `offset` like `mmap`, pages are read from the file on the first access.
+    /// Writes to a `shared` mapping go back to the file, otherwise they stay private.
+    pub fn mmap_file(
+        &mut self,
+        start: usize, len: usize, permission: MapPermission, max_permission: MapPermission,
+        inode: Arc<Inode>, offset: usize, shared: bool,
+    ) -> Option<usize> {
+        let start = self.check_mmap_range(start, len, PAGE_SIZE)?;
+        let mut map_area = MapArea::new(
+            start.into(),
+            (start + len).into(),
+            MapType::Lazy,
+            permission,
+        );
+        map_area.max_perm = max_permission;
+        map_area.backing = Some(MapBacking::File { inode, offset, shared });
+        self.areas.push(map_area);
+        Some(start)
+    }
+
+    /// Attach `segment` at `start` like `mmap`, its frames are shared with other attachments.
+    /// Return None if out of memory for the page tables as well.
+    pub fn attach_shm(
+        &mut self,
+        start: usize, permission: MapPermission, segment: Arc<ShmSegment>,
+    ) -> Option<usize> {
+        let len = segment.size();
+        let start = self.check_mmap_range(start, len, PAGE_SIZE)?;
+        let mut map_area = MapArea::new(
+            start.into(),
+            (start + len).into(),
+            MapType::Shared,
+            permission,
+        );
+        // a read-only attachment stays read-only
+        map_area.max_perm = permission | MapPermission::X;
+        map_area.backing = Some(MapBacking::Shm { segment, offset: 0 });
+        self.push(map_area, None)?;
+        Some(start)
+    }
+
+    /// Detach the shared memory attached at `start`, return false if there is none.
+    pub fn detach_shm(&mut self, start: usize) -> bool {
+        let start_vpn = VirtAddr::from(start).floor();
+        if self.areas.iter().any(|area| {
+            area.map_type == MapType::Shared && area.vpn_range.get_start() == start_vpn
+        }) {
+            self.remove_area_with_start_vpn(start_vpn);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Write dirty shared file pages in [start, start + len) back to their files.
+    /// Return false if some page in it is not mapped by a user area.
+    pub fn msync(&mut self, start: usize, len: usize) -> bool {
+        let start_vpn = VirtAddr::from(start).floor();
+        let end_vpn = VirtAddr::from(start + len).ceil();
+        if !self.is_user_range(start_vpn, end_vpn) {
+            return false;
+        }
+        for area in self.areas.iter_mut().filter(|area| area.overlaps(start_vpn, end_vpn)) {
+            // clean pages are read-only again to catch the next write
+            for vpn in area.write_back(start_vpn, end_vpn) {
+                self.page_table.remap(vpn, area.data_frames[&vpn].ppn, area.page_flags(vpn));
+            }
+        }
+        true
+    }
+
+    /// Move the end of the heap area starting at `heap_bottom` from `old_brk` to `new_brk`,
+    /// return false if the heap is gone, would overlap other areas or memory runs out.
+    pub fn change_brk(&mut self, heap_bottom: usize, old_brk: usize, new_brk: usize) -> bool {
+        if new_brk < heap_bottom || new_brk > USER_SPACE_END {
+            return false;
+        }
+        let start_vpn = VirtAddr::from(heap_bottom).floor();
+        let old_end_vpn = VirtAddr::from(old_brk).ceil();
+        let new_end_vpn = VirtAddr::from(new_brk).ceil();
+        if new_end_vpn > old_end_vpn
+            && self.areas.iter().any(|area| area.overlaps(old_end_vpn, new_end_vpn))
+        {
+            return false;
+        }
+        if let Some(area) = self
+            .areas
+            .iter_mut()
+            .find(|area| area.vpn_range.get_start() == start_vpn)
+        {
+            area.resize(&mut self.page_table, new_end_vpn).is_some()
+        } else {
+            false
+        }
+    }
+
+    /// Unmap [start, start + len), splitting areas crossing its boundaries. Frames pinned
+    /// by a syscall blocked on them are freed once it lets them go. Return false if some page in it is not mapped by a user area or it splits a huge page.
+    pub fn munmap(&mut self, start: usize, len: usize) -> bool {
+        let start_vpn = VirtAddr::from(start).floor();
+        let end_vpn = VirtAddr::from(start + len).ceil();
+        if !self.is_user_range(start_vpn, end_vpn)
+            || self.splits_huge_page(start_vpn)
+            || self.splits_huge_page(end_vpn)
+        {
+            return false;
+        }
+        self.split_area_at(start_vpn);
+        self.split_area_at(end_vpn);
+        let page_table = &mut self.page_table;
+        self.areas.retain_mut(|area| {
+            if area.overlaps(start_vpn, end_vpn) {
+                area.unmap(page_table);
+                false
+            } else {
+                true
+            }
+        });
+        true
+    }
+
+    /// Change the permission of [start, start + len), splitting areas crossing its boundaries.
+    /// Return false if some page in it is not mapped by a user area, it splits a huge page
+    /// or the permission is more than an area allows.
+    pub fn mprotect(&mut self, start: usize, len: usize, permission: MapPermission) -> bool {
+        let start_vpn = VirtAddr::from(start).floor();
+        let end_vpn = VirtAddr::from(start + len).ceil();
+        if !self.is_user_range(start_vpn, end_vpn)
+            || self.splits_huge_page(start_vpn)
+            || self.splits_huge_page(end_vpn)
+            || self.areas.iter().any(|area| {
+                area.overlaps(start_vpn, end_vpn) && !area.max_perm.contains(permission)
+            })
+        {
+            return false;
+        }
+        self.split_area_at(start_vpn);
+        self.split_area_at(end_vpn);
+        for area in self.areas.iter_mut().filter(|area| area.overlaps(start_vpn, end_vpn)) {
+            area.set_permission(&mut self.page_table, permission);
+        }
+        true
+    }
+
+    fn is_user_range(&self, start_vpn: VirtPageNum, end_vpn: VirtPageNum) -> bool {
+        VPNRange::new(start_vpn, end_vpn).into_iter().all(|vpn| {
+            self.areas
+                .iter()
+                .any(|area| area.map_perm.contains(MapPermission::U) && area.contains(vpn))
+        })
+    }
+
+    /// Areas of huge pages start at a huge page and are only split between them.
+    fn splits_huge_page(&self, vpn: VirtPageNum) -> bool {
+        vpn.0 % PageSize::MegaPage.pages() != 0
+            && self.areas.iter().any(|area| area.map_type == MapType::Huge && area.contains(vpn))
+    }
+
+    fn split_area_at(&mut self, vpn: VirtPageNum) {
+        if let Some(area) = self
+            .areas
+            .iter_mut()
+            .find(|area| area.vpn_range.get_start() < vpn && vpn < area.vpn_range.get_end())
+        {
+            let new_area = area.split_off(vpn);
+            self.areas.push(new_area);
+        }
+    }
+
+    /// Choose a free place aligned to `align` if `start` is 0,
+    /// return None if the range is taken or too high.
+    fn check_mmap_range(&self, start: usize, len: usize, align: usize) -> Option<usize> {
+        let start = if start == 0 { self.find_free_area(len, align) } else { start };
+        if start.checked_add(len).is_none_or(|end| end > USER_SPACE_END) {
+            return None;
+        }
+        let start_vpn = VirtAddr::from(start).floor();
+        let end_vpn = VirtAddr::from(start + len).ceil();
+        if self.areas.iter().any(|area| area.overlaps(start_vpn, end_vpn)) {
+            return None;
+        }
+        Some(start)
+    }
+
+    fn find_free_area(&self, len: usize, align: usize) -> usize {
+        let mut start = MMAP_BASE;
+        loop {
+            let start_vpn = VirtAddr::from(start).floor();
+            let end_vpn = VirtAddr::from(start + len).ceil();
+            if let Some(area) = self.areas.iter().find(|area| area.overlaps(start_vpn, end_vpn)) {
+                start = usize::from(VirtAddr::from(area.vpn_range.get_end())).next_multiple_of(align);
+            } else {
+                return start;
+            }
+        }
+    }
+
+    /// Frames held by user areas, including those shared with other processes.
+    pub fn resident_pages(&self) -> usize {
+        self.areas
+            .iter()
+            .filter(|area| area.map_perm.contains(MapPermission::U))
+            .flat_map(|area| area.data_frames.values())
+            .filter(|frame| !Arc::ptr_eq(frame, &ZERO_FRAME))
+            .count()
+    }
+
+    /// Share read-only private pages with the same content as pages in `pages`,
+    /// which maps content hashes to frames, and add the others to it.
+    /// Return the number of frames freed.
+    pub fn merge_identical_pages(&mut self, pages: &mut BTreeMap<u64, Vec<Arc<FrameTracker>>>) -> usize {
+        let mut freed = 0;
+        for area in self.areas.iter_mut() {
+            // writable pages would have to be copied on writes again
+            if !area.is_evictable() || area.map_perm.contains(MapPermission::W) {
+                continue;
+            }
+            for (vpn, frame) in area.data_frames.iter_mut() {
+                let bytes = frame.ppn.get_bytes_array();
+                let same = pages.entry(page_hash(bytes)).or_default();
+                match same.iter().find(|other| other.ppn.get_bytes_array() == bytes) {
+                    Some(other) if !Arc::ptr_eq(other, frame) => {
+                        if Arc::strong_count(frame) == 1 {
+                            freed += 1;
+                        }
+                        *frame = other.clone();
+                        self.page_table.remap(*vpn, frame.ppn, PTEFlags::from_bits(area.map_perm.bits).unwrap());
+                    }
+                    Some(_) => {}
+                    None => same.push(frame.clone()),
+                }
+            }
+        }
+        freed
+    }
+
     pub fn recycle_data_pages(&mut self) {
         self.areas.clear();
     }
     
     pub fn new_kernel() -> Self {
-        let mut memory_set = Self::new_bare();
+        let mut memory_set = Self::with_page_table(PageTable::new_kernel());
         // map trampoline
-        memory_set.map_trampoline();
+        memory_set.map_trampoline().unwrap();
         // map kernel sections
         println!("[kernel] .text [{:#x}, {:#x})", stext as usize, etext as usize);
         println!("[kernel] .rodata [{:#x}, {:#x})", srodata as usize, erodata as usize);
@@ -201,35 +935,35 @@ impl MemorySet {
             (etext as usize).into(),
             MapType::Identical,
             MapPermission::R | MapPermission::X,
-        ), None);
+        ), None).unwrap();
         println!("[kernel] mapping .rodata section");
         memory_set.push(MapArea::new(
             (srodata as usize).into(),
             (erodata as usize).into(),
             MapType::Identical,
             MapPermission::R,
-        ), None);
+        ), None).unwrap();
         println!("[kernel] mapping .data section");
         memory_set.push(MapArea::new(
             (sdata as usize).into(),
             (edata as usize).into(),
             MapType::Identical,
             MapPermission::R | MapPermission::W,
-        ), None);
+        ), None).unwrap();
         println!("[kernel] mapping .bss section");
         memory_set.push(MapArea::new(
             (sbss_with_stack as usize).into(),
             (ebss as usize).into(),
             MapType::Identical,
             MapPermission::R | MapPermission::W,
-        ), None);
+        ), None).unwrap();
         println!("[kernel] mapping physical memory");
         memory_set.push(MapArea::new(
             (ekernel as usize).into(),
             MEMORY_END.into(),
             MapType::Identical,
             MapPermission::R | MapPermission::W,
-        ), None);
+        ), None).unwrap();
         println!("[kernel] mapping memory-mapped registers");
         for pair in MMIO_VIRT_IO {
             println!("[kernel] - MMIO_VIRT_IO: {:?}", pair);
@@ -238,7 +972,7 @@ impl MemorySet {
                 ((*pair).0 + (*pair).1).into(),
                 MapType::Identical,
                 MapPermission::R | MapPermission::W,
-            ), None);
+            ), None).unwrap();
         }
         println!("[kernel] - MMIO_VIRT_UART: {:?}", MMIO_VIRT_UART);
         memory_set.push(MapArea::new(
@@ -246,23 +980,32 @@ impl MemorySet {
             (MMIO_VIRT_UART.0 + MMIO_VIRT_UART.1).into(),
             MapType::Identical,
             MapPermission::R | MapPermission::W,
-        ), None);
+        ), None).unwrap();
         println!("[kernel] - MMIO_VIRT_TEST: {:?}", MMIO_VIRT_TEST);
         memory_set.push(MapArea::new(
             MMIO_VIRT_TEST.0.into(),
             (MMIO_VIRT_TEST.0 + MMIO_VIRT_TEST.1).into(),
             MapType::Identical,
             MapPermission::R | MapPermission::W,
-        ), None);
+        ), None).unwrap();
+        println!("[kernel] - MMIO_VIRT_MSIP: {:?}", MMIO_VIRT_MSIP);
+        memory_set.push(MapArea::new(
+            MMIO_VIRT_MSIP.0.into(),
+            (MMIO_VIRT_MSIP.0 + MMIO_VIRT_MSIP.1).into(),
+            MapType::Identical,
+            MapPermission::R | MapPermission::W,
+        ), None).unwrap();
         memory_set
     }
     
-    /// Include section in elf and trampoline and TrapContext and user stack
-    /// also returns uer_sp and entry point.
-    pub fn from_elf(elf_data: &[u8]) -> (Self, usize, usize) {
-        let mut memory_set = Self::new_bare();
+    /// Include section in elf and trampoline and an empty heap,
+    /// also returns user stack base, heap bottom and entry point.
+    /// Return None if out of memory.
+    /// Pages of the segments are read from `inode`, the file `elf_data` was read from.
+    pub fn from_elf(elf_data: &[u8], inode: Arc<Inode>) -> Option<(Self, usize, usize, usize)> {
+        let mut memory_set = Self::new_bare()?;
         // map trampoline
-        memory_set.map_trampoline();
+        memory_set.map_trampoline()?;
         // map program headers of elf, with U flag
         let elf = xmas_elf::ElfFile::new(elf_data).unwrap();
         let elf_header = elf.header;
@@ -280,45 +1023,258 @@ impl MemorySet {
                 if ph_flags.is_read() { map_perm |= MapPermission::R; }
                 if ph_flags.is_write() { map_perm |= MapPermission::W; }
                 if ph_flags.is_execute() { map_perm |= MapPermission::X; }
-                let map_area = MapArea::new(
+                let mut map_area = MapArea::new(
                     start_va,
                     end_va,
-                    MapType::Framed,
+                    MapType::Lazy,
                     map_perm,
                 );
+                if ph.file_size() > 0 {
+                    // fill pages from the file on demand
+                    map_area.backing = Some(MapBacking::Elf {
+                        inode: Arc::clone(&inode),
+                        offset: ph.offset() as usize,
+                        len: ph.file_size() as usize,
+                    });
+                }
                 max_end_vpn = map_area.vpn_range.get_end();
-                memory_set.push(
-                    map_area,
-                    Some(&elf_data[ph.offset() as usize..(ph.offset() + ph.file_size()) as usize])
-                );
+                memory_set.push(map_area, None)?;
             }
         }
-        // map user stack with U flags
-        let max_end_va: VirtAddr = max_end_vpn.into();
-        let mut user_stack_base: usize = max_end_va.into();
-        // guard page
-        user_stack_base += PAGE_SIZE;
-        (memory_set, user_stack_base, elf_header.pt2.entry_point() as usize)
+        // the heap starts empty right after the highest segment and grows by brk
+        let heap_bottom: usize = VirtAddr::from(max_end_vpn).into();
+        memory_set.insert_lazy_area(
+            heap_bottom.into(),
+            heap_bottom.into(),
+            MapPermission::R | MapPermission::W | MapPermission::U,
+        );
+        Some((memory_set, USER_STACK_BASE, heap_bottom, elf_header.pt2.entry_point() as usize))
     }
 
-    pub fn from_existed_user(user_space: &MemorySet) -> MemorySet {
-        let mut memory_set = Self::new_bare();
+    /// User pages are shared read-only with the parent and copied on the first write,
+    /// while trap contexts are copied eagerly since the kernel writes them through physical addresses.
+    /// Return None if out of memory.
+    pub fn from_existed_user(user_space: &mut MemorySet) -> Option<MemorySet> {
+        let mut memory_set = Self::new_bare()?;
         // map trampoline
-        memory_set.map_trampoline();
-        // copy data sections/trap_context/user_stack
+        memory_set.map_trampoline()?;
+        // share data sections/user_stack and copy trap_context
         for area in user_space.areas.iter() {
-            let new_area = MapArea::from_another(area);
-            memory_set.push(new_area, None);
-            // copy data from another space
-            for vpn in area.vpn_range {
-                let src_ppn = user_space.translate(vpn).unwrap().ppn();
-                let dst_ppn = memory_set.translate(vpn).unwrap().ppn();
-                dst_ppn.get_bytes_array().copy_from_slice(src_ppn.get_bytes_array());
+            if area.map_type == MapType::Huge {
+                // huge pages are copied at once instead of page by page on writes
+                let mut new_area = MapArea::from_another(area);
+                for (vpn, frame) in area.data_frames.iter() {
+                    if !new_area.data_frames.contains_key(vpn)
+                        && new_area.load_page(&mut memory_set.page_table, *vpn, true) != PageFault::Handled
+                    {
+                        return None;
+                    }
+                    new_area.data_frames[vpn].ppn.get_bytes_array().copy_from_slice(frame.ppn.get_bytes_array());
+                }
+                memory_set.areas.push(new_area);
+            } else if area.map_type != MapType::Identical && area.map_perm.contains(MapPermission::U) {
+                let mut new_area = MapArea::from_another(area);
+                let pte_flags = PTEFlags::from_bits(area.map_perm.bits).unwrap() - PTEFlags::W;
+                for (vpn, frame) in area.data_frames.iter() {
+                    // pages of shared file mappings and shared memory stay writable in the parent
+                    if area.map_perm.contains(MapPermission::W)
+                        && !area.is_shared()
+                        && area.map_type != MapType::Shared
+                    {
+                        user_space.page_table.remap(*vpn, frame.ppn, pte_flags);
+                    }
+                    // and shared memory is writable in the child too
+                    let pte_flags = if area.map_type == MapType::Shared {
+                        area.page_flags(*vpn)
+                    } else {
+                        pte_flags
+                    };
+                    // the area is not pushed yet, so its frames go away with it on failure
+                    memory_set.page_table.map(*vpn, frame.ppn, pte_flags)?;
+                    new_area.data_frames.insert(*vpn, Arc::clone(frame));
+                }
+                // share the slots of swapped out pages, but not the clean copies of
+                // present pages, which may be dirty in the parent
+                for (vpn, slot) in area.swapped.iter() {
+                    if !area.data_frames.contains_key(vpn) {
+                        new_area.swapped.insert(*vpn, Arc::clone(slot));
+                    }
+                }
+                memory_set.areas.push(new_area);
+            } else {
+                let new_area = MapArea::from_another(area);
+                memory_set.push(new_area, None)?;
+                // copy data from another space
+                for vpn in area.vpn_range {
+                    let src_ppn = user_space.translate(vpn).unwrap().ppn();
+                    let dst_ppn = memory_set.translate(vpn).unwrap().ppn();
+                    dst_ppn.get_bytes_array().copy_from_slice(src_ppn.get_bytes_array());
+                }
             }
         }
-        memory_set
+        Some(memory_set)
+    }
+
+    /// Resolve a page fault caused by lazy mapping, swapping or copy-on-write.
+    pub fn handle_page_fault(&mut self, vpn: VirtPageNum, is_store: bool) -> PageFault {
+        // frames the kernel heap does not use are cheaper to take back than swapping
+        while frame_free_count() < FAULT_FRAMES
+            && (shrink_heap() > 0 || self.swap_out_one() || reclaim_frame())
+        {}
+        if !self.areas.iter().any(|area| area.contains(vpn)) {
+            let fault = self.grow_stack(vpn);
+            if fault != PageFault::Handled {
+                return fault;
+            }
+        }
+        if let Some(area) = self
+            .areas
+            .iter_mut()
+            .find(|area| area.contains(vpn))
+        {
+            match area.load_page(&mut self.page_table, vpn, is_store) {
+                PageFault::Invalid if is_store => area.handle_store_fault(&mut self.page_table, vpn),
+                result => result,
+            }
+        } else {
+            PageFault::Invalid
+        }
+    }
+
+    /// Whether a fault of `access` on `vpn` needs nothing done, since another hart has
+    /// loaded the page meanwhile or this hart cached its entry from before it was mapped.
+    pub fn is_spurious_fault(&self, vpn: VirtPageNum, access: MapPermission) -> bool {
+        let flags = PTEFlags::from_bits(access.bits).unwrap() | PTEFlags::V;
+        if self.page_table.translate(vpn).is_some_and(|pte| pte.flags().contains(flags)) {
+            self.page_table.flush_local(vpn);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Extend the stack area right above `vpn` down to it. It is a stack overflow
+    /// if `vpn` is in the guard page below the limit or other areas are in the way.
+    fn grow_stack(&mut self, vpn: VirtPageNum) -> PageFault {
+        let Some(area) = self.areas.iter().find(|area| {
+            area.stack_bottom
+                .is_some_and(|bottom| bottom.0 <= vpn.0 + 1 && vpn < area.vpn_range.get_start())
+        }) else {
+            return PageFault::Invalid;
+        };
+        let start_vpn = area.vpn_range.get_start();
+        if vpn < area.stack_bottom.unwrap()
+            || self.areas.iter().any(|area| area.overlaps(vpn, start_vpn))
+        {
+            return PageFault::StackOverflow;
+        }
+        let area = self
+            .areas
+            .iter_mut()
+            .find(|area| area.vpn_range.get_start() == start_vpn)
+            .unwrap();
+        // lazy, so the new pages are loaded as they are accessed
+        area.vpn_range = VPNRange::new(vpn, area.vpn_range.get_end());
+        PageFault::Handled
+    }
+
+    /// Make [start, start + len) present, and private if `write`, before the kernel
+    /// accesses it through physical addresses. Return false if out of memory.
+    pub fn prepare_user_access(&mut self, start: usize, len: usize, write: bool) -> bool {
+        let start_vpn = VirtAddr::from(start).floor();
+        let end_vpn = VirtAddr::from(start + len).ceil();
+        for vpn in VPNRange::new(start_vpn, end_vpn) {
+            if self.handle_page_fault(vpn, write) == PageFault::OutOfMemory {
+                return false;
+            }
+            if write {
+                if let Some(area) = self.areas.iter_mut().find(|area| area.contains(vpn)) {
+                    area.drop_swap_copy(vpn);
+                }
+            }
+        }
+        true
+    }
+
+    /// The frames behind [start, start + len), which is present, for the kernel to keep
+    /// while it accesses the range without holding the address space.
+    pub fn pin_user_pages(&self, start: usize, len: usize) -> Vec<Arc<FrameTracker>> {
+        let start_vpn = VirtAddr::from(start).floor();
+        let end_vpn = VirtAddr::from(start + len).ceil();
+        VPNRange::new(start_vpn, end_vpn)
+            .into_iter()
+            .filter_map(|vpn| {
+                let area = self.areas.iter().find(|area| area.contains(vpn))?;
+                area.data_frames.get(&vpn).cloned()
+            })
+            .collect()
+    }
+
+    /// Evict a resident private user page with the clock algorithm, pages accessed
+    /// since the last sweep get a second chance. Return false if none can be evicted.
+    pub fn swap_out_one(&mut self) -> bool {
+        let resident: usize = self
+            .areas
+            .iter()
+            .filter(|area| area.is_evictable())
+            .map(|area| area.data_frames.len())
+            .sum();
+        // a page is visited at most twice: once to clear its accessed bit
+        for _ in 0..2 * resident {
+            let Some((idx, vpn)) = self
+                .next_resident(self.clock_hand)
+                .or_else(|| self.next_resident(VirtPageNum(0)))
+            else {
+                return false;
+            };
+            self.clock_hand = VirtPageNum(vpn.0 + 1);
+            let area = &mut self.areas[idx];
+            // frames shared by fork or pinned by a syscall are kept
+            if Arc::strong_count(&area.data_frames[&vpn]) > 1
+                || self.page_table.clear_accessed(vpn)
+            {
+                continue;
+            }
+            return area.swap_out(&mut self.page_table, vpn);
+        }
+        false
+    }
+
+    fn next_resident(&self, from: VirtPageNum) -> Option<(usize, VirtPageNum)> {
+        self.areas
+            .iter()
+            .enumerate()
+            .filter(|(_, area)| area.is_evictable())
+            .filter_map(|(idx, area)| {
+                area.data_frames.range(from..).next().map(|(vpn, _)| (idx, *vpn))
+            })
+            .min_by_key(|(_, vpn)| vpn.0)
     }
 
+    /// Make a NUL-terminated user string present before the kernel reads it.
+    /// Return false if out of memory.
+    pub fn prepare_user_str(&mut self, ptr: usize) -> bool {
+        let mut va = VirtAddr::from(ptr);
+        loop {
+            let mut vpn = va.floor();
+            if self.handle_page_fault(vpn, false) == PageFault::OutOfMemory {
+                return false;
+            }
+            match self.translate(vpn) {
+                Some(pte) if pte.is_valid() => {
+                    if pte.ppn().get_bytes_array()[va.page_offset()..].contains(&0) {
+                        break;
+                    }
+                }
+                _ => break,
+            }
+            vpn.step();
+            va = vpn.into();
+        }
+        true
+    }
+
+    /// Turn on paging with the kernel space at boot, user spaces are switched to in `__restore`.
     pub fn activate(&self) {
         let satp = self.page_table.token();
         unsafe {
@@ -336,21 +1292,39 @@ impl MemorySet {
     }
     
     /// Mention that trampoline is not collected by areas.
-    fn map_trampoline(&mut self) {
+    fn map_trampoline(&mut self) -> Option<()> {
         self.page_table.map(
             VirtAddr::from(TRAMPOLINE).into(),
             PhysAddr::from(strampoline as usize).into(),
             PTEFlags::R | PTEFlags::X,
-        );
+        )
     }
 }
 
 lazy_static! {
-    pub static ref KERNEL_SPACE: Arc<UPSafeCell<MemorySet>> = Arc::new(unsafe {
-        UPSafeCell::new(MemorySet::new_kernel())
-    });
+    pub static ref KERNEL_SPACE: Arc<SpinLock<MemorySet>> = Arc::new(SpinLock::new(MemorySet::new_kernel()));
+}
+
+lazy_static! {
+    /// Shared by pages read before they are written, which are copied on the first write.
+    static ref ZERO_FRAME: Arc<FrameTracker> = Arc::new(frame_alloc().unwrap());
+}
+
+/// FNV-1a over the words of a page, to find pages that may be the same
+fn page_hash(bytes: &[u8]) -> u64 {
+    bytes.chunks_exact(8).fold(0xcbf2_9ce4_8422_2325, |hash, word| {
+        (hash ^ u64::from_le_bytes(word.try_into().unwrap())).wrapping_mul(0x100_0000_01b3)
+    })
+}
+
+/// Content hashes of the zero frame to seed `MemorySet::merge_identical_pages` with,
+/// so that pages left zero go back to it.
+pub fn zero_page_table() -> BTreeMap<u64, Vec<Arc<FrameTracker>>> {
+    let mut pages = BTreeMap::new();
+    pages.insert(page_hash(ZERO_FRAME.ppn.get_bytes_array()), alloc::vec![ZERO_FRAME.clone()]);
+    pages
 }
 
 pub fn kernel_token() -> usize {
-    KERNEL_SPACE.exclusive_access().token()
+    KERNEL_SPACE.lock().token()
 }
\ No newline at end of file
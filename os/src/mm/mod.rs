@@ -1,16 +1,31 @@
 mod address;
+mod asid;
 mod page_table;
 mod frame_allocator;
 mod memory_set;
 mod heap_allocator;
+mod shm;
+mod swap;
 
-pub use memory_set::{KERNEL_SPACE, MemorySet, MapPermission, kernel_token};
-pub use address::{StepByOne, PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
+pub use memory_set::{KERNEL_SPACE, MemorySet, MapPermission, PageFault, kernel_token, zero_page_table};
+pub use address::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
 pub use page_table::{PageTable, translated_byte_buffer, translated_str, translated_ref, translated_refmut, UserBuffer};
-pub use frame_allocator::{frame_alloc, frame_dealloc, FrameTracker};
+pub use frame_allocator::{frame_alloc_contiguous, frame_stats, FrameTracker};
+pub use shm::SHM_MANAGER;
+
+use crate::ipi::set_online;
 
 pub fn init() {
     heap_allocator::init_heap();
     frame_allocator::init_frame_allocator();
-    KERNEL_SPACE.exclusive_access().activate();
+    // before paging is on, so no entry this hart caches misses a shootdown
+    set_online();
+    KERNEL_SPACE.lock().activate();
+    asid::init();
+}
+
+/// Turn on paging on the other harts once the boot hart has set up the kernel space.
+pub fn init_other_hart() {
+    set_online();
+    KERNEL_SPACE.lock().activate();
 }
\ No newline at end of file
@@ -1,10 +1,15 @@
 use alloc::string::String;
+use alloc::sync::Arc;
 use alloc::vec;
 use alloc::vec::Vec;
+use core::cell::Cell;
 use bitflags::*;
+use crate::config::{PAGING_LEVELS, SATP_MODE};
 use crate::mm::address::{PhysPageNum, StepByOne, VirtPageNum};
+use crate::mm::asid::{flush_kernel_page, flush_page, AsidHandle, KERNEL_ASID};
 use crate::mm::frame_allocator::{frame_alloc, FrameTracker};
 use crate::mm::{PhysAddr, VirtAddr};
+use crate::task::hart_id;
 
 bitflags! {
     pub struct PTEFlags: u8 {
@@ -54,46 +59,175 @@ impl PageTableEntry {
     pub fn executable(&self) -> bool {
         (self.flags() & PTEFlags::X) != PTEFlags::empty()
     }
+    pub fn accessed(&self) -> bool {
+        (self.flags() & PTEFlags::A) != PTEFlags::empty()
+    }
+    pub fn dirty(&self) -> bool {
+        (self.flags() & PTEFlags::D) != PTEFlags::empty()
+    }
+    /// A valid entry pointing to a page rather than the next level table
+    pub fn is_leaf(&self) -> bool {
+        self.flags().intersects(PTEFlags::R | PTEFlags::W | PTEFlags::X)
+    }
+}
+
+/// Leaf entries map 4 KiB pages at the lowest level and larger pages above it.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum PageSize {
+    Page,
+    /// 2 MiB
+    MegaPage,
+    /// 1 GiB
+    GigaPage,
+    /// 512 GiB
+    #[cfg(feature = "sv48")]
+    TeraPage,
+}
+
+impl PageSize {
+    /// Number of 4 KiB pages it covers
+    pub fn pages(self) -> usize {
+        1 << (9 * self.level())
+    }
+
+    /// Levels above the lowest one
+    fn level(self) -> usize {
+        match self {
+            PageSize::Page => 0,
+            PageSize::MegaPage => 1,
+            PageSize::GigaPage => 2,
+            #[cfg(feature = "sv48")]
+            PageSize::TeraPage => 3,
+        }
+    }
+
+    fn from_level(level: usize) -> Self {
+        match level {
+            0 => PageSize::Page,
+            1 => PageSize::MegaPage,
+            2 => PageSize::GigaPage,
+            #[cfg(feature = "sv48")]
+            3 => PageSize::TeraPage,
+            _ => unreachable!(),
+        }
+    }
 }
 
 pub struct PageTable {
     root_ppn: PhysPageNum,
     frames: Vec<FrameTracker>,
+    /// tags the TLB entries of a user page table, None for the kernel space
+    asid: Option<AsidHandle>,
+    /// harts that have taken its token and may have cached its entries
+    harts: Cell<usize>,
 }
 
 impl PageTable {
-    pub fn new() -> Self {
+    /// Return None if out of memory
+    pub fn new() -> Option<Self> {
+        let frame = frame_alloc()?;
+        Some(PageTable {
+            root_ppn: frame.ppn,
+            frames: vec![frame],
+            asid: Some(AsidHandle::new()),
+            harts: Cell::new(0),
+        })
+    }
+
+    pub fn new_kernel() -> Self {
         let frame = frame_alloc().unwrap();
         PageTable {
             root_ppn: frame.ppn,
             frames: vec![frame],
+            asid: None,
+            harts: Cell::new(0),
         }
     }
     
-    pub fn map(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) {
-        let pte = self.find_pte_create(vpn).unwrap();
+    /// Return None if out of memory for the page tables
+    pub fn map(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) -> Option<()> {
+        self.map_huge(vpn, ppn, flags, PageSize::Page)
+    }
+
+    /// Map a page of `size` with both `vpn` and `ppn` aligned to it,
+    /// return None if out of memory for the page tables.
+    pub fn map_huge(
+        &mut self,
+        vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags, size: PageSize,
+    ) -> Option<()> {
+        assert!(vpn.0 % size.pages() == 0 && ppn.0 % size.pages() == 0, "{:?} is not aligned", size);
+        let pte = self.find_pte_create(vpn, size)?;
         assert!(!pte.is_valid(), "vpn {:?} is mapped before mapping", vpn);
         *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+        // invalid entries may be cached as well, another hart faulting on it finds it valid
+        self.flush_local(vpn);
+        Some(())
     }
     
+    /// Change the frame and flags of an existing mapping,
+    /// the accessed and dirty bits are kept since the content does not change.
+    /// A huge page is remapped through its first page.
+    pub fn remap(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) {
+        let (pte, size) = self.find_pte(vpn).unwrap();
+        assert!(pte.is_valid(), "vpn {:?} is invalid before remapping", vpn);
+        assert_eq!(vpn.0 % size.pages(), 0, "vpn {:?} is inside a huge page", vpn);
+        let kept = pte.flags() & (PTEFlags::A | PTEFlags::D);
+        *pte = PageTableEntry::new(ppn, flags | kept | PTEFlags::V);
+        self.flush(vpn);
+    }
+
+    /// Clear the accessed bit of a mapping and return whether it was set.
+    pub fn clear_accessed(&mut self, vpn: VirtPageNum) -> bool {
+        let (pte, _) = self.find_pte(vpn).unwrap();
+        let accessed = pte.accessed();
+        *pte = PageTableEntry::new(pte.ppn(), pte.flags() - PTEFlags::A);
+        // or the cached entry keeps the page from being marked again
+        self.flush(vpn);
+        accessed
+    }
+
+    /// A huge page is unmapped through its first page.
     pub fn unmap(&mut self, vpn: VirtPageNum) {
-        let pte = self.find_pte(vpn).unwrap();
+        let (pte, size) = self.find_pte(vpn).unwrap();
         assert!(pte.is_valid(), "vpn {:?} is invalid before unmapping", vpn);
+        assert_eq!(vpn.0 % size.pages(), 0, "vpn {:?} is inside a huge page", vpn);
         *pte = PageTableEntry::empty();
+        self.flush(vpn);
+    }
+
+    /// Only entries of this page table are flushed, other address spaces keep theirs.
+    /// They are shot down on the other harts that may have cached them.
+    fn flush(&self, vpn: VirtPageNum) {
+        let va: VirtAddr = vpn.into();
+        match &self.asid {
+            Some(asid) => asid.shootdown_page(va.into(), self.harts.get()),
+            None => flush_kernel_page(va.into()),
+        }
+    }
+
+    /// Flush the entry of `vpn` on this hart only.
+    pub fn flush_local(&self, vpn: VirtPageNum) {
+        let va: VirtAddr = vpn.into();
+        match &self.asid {
+            Some(asid) => asid.flush_page(va.into()),
+            None => flush_page(va.into(), KERNEL_ASID),
+        }
     }
     
-    fn find_pte_create(&mut self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
+    /// Find the entry of a page of `size`, creating the tables above it.
+    fn find_pte_create(&mut self, vpn: VirtPageNum, size: PageSize) -> Option<&mut PageTableEntry> {
         let idxs = vpn.indexes();
         let mut ppn = self.root_ppn;
         let mut result: Option<&mut PageTableEntry> = None;
-        for i in 0..3 {
+        for i in 0..PAGING_LEVELS {
             let pte = &mut ppn.get_pte_array()[idxs[i]];
-            if i == 2 {
+            if i == PAGING_LEVELS - 1 - size.level() {
                 result = Some(pte);
                 break;
             }
+            assert!(!pte.is_leaf(), "vpn {:?} is in a huge page before mapping", vpn);
             if !pte.is_valid() {
-                let frame = frame_alloc().unwrap();
+                let frame = frame_alloc()?;
                 *pte = PageTableEntry::new(frame.ppn, PTEFlags::V);
                 self.frames.push(frame);
             }
@@ -102,14 +236,16 @@ impl PageTable {
         result
     }
     
-    fn find_pte(&self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
+    /// Find the entry of the page holding `vpn`, which may be a huge page, and its size.
+    fn find_pte(&self, vpn: VirtPageNum) -> Option<(&mut PageTableEntry, PageSize)> {
         let idxs = vpn.indexes();
         let mut ppn = self.root_ppn;
-        let mut result: Option<&mut PageTableEntry> = None;
-        for i in 0..3 {
+        let mut result: Option<(&mut PageTableEntry, PageSize)> = None;
+        for i in 0..PAGING_LEVELS {
             let pte = &mut ppn.get_pte_array()[idxs[i]];
-            if i == 2 {
-                result = Some(pte);
+            let size = PageSize::from_level(PAGING_LEVELS - 1 - i);
+            if size == PageSize::Page || pte.is_leaf() {
+                result = Some((pte, size));
                 break;
             }
             if !pte.is_valid() {
@@ -125,16 +261,28 @@ impl PageTable {
         Self {
             root_ppn: PhysPageNum::from(satp & ((1usize << 44) - 1)),
             frames: Vec::new(),
+            asid: None,
+            harts: Cell::new(0),
         }
     }
     
+    /// The entry of a page in a huge page is made up with the frame of that page.
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
+        self.find_pte(vpn).map(|(pte, size)| {
+            let ppn = PhysPageNum(pte.ppn().0 + vpn.0 % size.pages());
+            PageTableEntry::new(ppn, pte.flags())
+        })
+    }
+
+    /// Size of the page holding `vpn` if it is mapped
+    pub fn page_size(&self, vpn: VirtPageNum) -> Option<PageSize> {
         self.find_pte(vpn)
-            .map(|pte| pte.clone())
+            .filter(|(pte, _)| pte.is_valid())
+            .map(|(_, size)| size)
     }
     
     pub fn translate_va(&self, va: VirtAddr) -> Option<PhysAddr> {
-        self.find_pte(va.clone().floor()).map(|pte| {
+        self.translate(va.clone().floor()).map(|pte| {
             let aligned_pa: PhysAddr = pte.ppn().into();
             let offset = va.page_offset();
             let aligned_pa_usize: usize = aligned_pa.into();
@@ -142,8 +290,13 @@ impl PageTable {
         })
     }
     
+    /// The id of a user page table is taken again here after a generation rollover,
+    /// so the token should be read right before switching to it.
+    /// The hart taking it is noted as one that may run the page table.
     pub fn token(&self) -> usize {
-        8usize << 60 | self.root_ppn.0
+        self.harts.set(self.harts.get() | 1 << hart_id());
+        let asid = self.asid.as_ref().map_or(KERNEL_ASID, |asid| asid.id());
+        SATP_MODE << 60 | asid << 44 | self.root_ppn.0
     }
 }
 
@@ -212,11 +365,14 @@ pub fn translated_refmut<T>(token: usize, ptr: *mut T) -> &'static mut T {
 
 pub struct UserBuffer {
     pub buffers: Vec<&'static mut [u8]>,
+    /// the frames behind `buffers`, held so they are neither freed nor swapped out
+    /// while a syscall blocks with the address space unlocked
+    frames: Vec<Arc<FrameTracker>>,
 }
 
 impl UserBuffer {
-    pub fn new(buffers: Vec<&'static mut [u8]>) -> Self {
-        Self { buffers }
+    pub fn new(buffers: Vec<&'static mut [u8]>, frames: Vec<Arc<FrameTracker>>) -> Self {
+        Self { buffers, frames }
     }
     
     pub fn len(&self) -> usize {
@@ -235,6 +391,7 @@ impl IntoIterator for UserBuffer {
         // 所有权转移
         UserBufferIterator {
             buffers: self.buffers,
+            _frames: self.frames,
             current_buffer: 0,
             current_idx: 0,
         }
@@ -243,6 +400,7 @@ impl IntoIterator for UserBuffer {
 
 pub struct UserBufferIterator {
     buffers: Vec<&'static mut [u8]>,
+    _frames: Vec<Arc<FrameTracker>>,
     current_buffer: usize,
     current_idx: usize,
 }
@@ -0,0 +1,121 @@
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use lazy_static::*;
+use crate::config::PAGE_SIZE;
+use crate::sync::UPSafeCell;
+use super::frame_allocator::{frame_alloc, FrameTracker};
+
+/// key that always creates a new segment
+pub const IPC_PRIVATE: usize = 0;
+
+/// Frames of a shared memory segment, they are released when the last attachment
+/// goes away, or when the segment is removed if it has never been attached.
+pub struct ShmSegment {
+    key: usize,
+    frames: Vec<Arc<FrameTracker>>,
+}
+
+impl ShmSegment {
+    /// Return None if out of memory
+    fn new(key: usize, page_count: usize) -> Option<Self> {
+        Some(Self {
+            key,
+            frames: (0..page_count)
+                .map(|_| frame_alloc().map(Arc::new))
+                .collect::<Option<Vec<_>>>()?,
+        })
+    }
+
+    pub fn size(&self) -> usize {
+        self.frames.len() * PAGE_SIZE
+    }
+
+    pub fn frame(&self, page_idx: usize) -> Arc<FrameTracker> {
+        Arc::clone(&self.frames[page_idx])
+    }
+}
+
+/// A segment is kept by the manager until it is first attached, then by its attachments only.
+enum ShmEntry {
+    Created(Arc<ShmSegment>),
+    Attached(Weak<ShmSegment>),
+}
+
+impl ShmEntry {
+    fn segment(&self) -> Option<Arc<ShmSegment>> {
+        match self {
+            Self::Created(segment) => Some(Arc::clone(segment)),
+            Self::Attached(segment) => segment.upgrade(),
+        }
+    }
+}
+
+/// Segments indexed by id, an id is reused after its segment is removed or released.
+pub struct ShmManager {
+    segments: Vec<Option<ShmEntry>>,
+}
+
+impl ShmManager {
+    pub fn new() -> Self {
+        Self { segments: Vec::new() }
+    }
+
+    /// Return the id of the segment with `key`, creating it if it does not exist.
+    /// Return None if an existing segment is smaller than `size` or out of memory.
+    pub fn get(&mut self, key: usize, size: usize) -> Option<usize> {
+        if key != IPC_PRIVATE {
+            if let Some((id, segment)) = self
+                .segments
+                .iter()
+                .enumerate()
+                .filter_map(|(id, entry)| Some((id, entry.as_ref()?.segment()?)))
+                .find(|(_, segment)| segment.key == key)
+            {
+                return if size <= segment.size() { Some(id) } else { None };
+            }
+        }
+        if size == 0 {
+            return None;
+        }
+        let segment = Some(ShmEntry::Created(Arc::new(ShmSegment::new(key, size.div_ceil(PAGE_SIZE))?)));
+        if let Some(id) = self
+            .segments
+            .iter()
+            .position(|entry| entry.as_ref().and_then(ShmEntry::segment).is_none())
+        {
+            self.segments[id] = segment;
+            Some(id)
+        } else {
+            self.segments.push(segment);
+            Some(self.segments.len() - 1)
+        }
+    }
+
+    pub fn segment(&self, id: usize) -> Option<Arc<ShmSegment>> {
+        self.segments.get(id)?.as_ref()?.segment()
+    }
+
+    /// `segment` with `id` has been attached, from now on it goes away with its last attachment.
+    pub fn set_attached(&mut self, id: usize, segment: &Arc<ShmSegment>) {
+        if let Some(Some(entry)) = self.segments.get_mut(id) {
+            if entry.segment().is_some_and(|seg| Arc::ptr_eq(&seg, segment)) {
+                *entry = ShmEntry::Attached(Arc::downgrade(segment));
+            }
+        }
+    }
+
+    /// Remove the segment from the namespace, its frames live on in existing attachments.
+    pub fn remove(&mut self, id: usize) -> bool {
+        self.segments
+            .get_mut(id)
+            .and_then(|entry| entry.take())
+            .and_then(|entry| entry.segment())
+            .is_some()
+    }
+}
+
+lazy_static! {
+    pub static ref SHM_MANAGER: UPSafeCell<ShmManager> = unsafe {
+        UPSafeCell::new(ShmManager::new())
+    };
+}
@@ -0,0 +1,76 @@
+use alloc::vec::Vec;
+use easy_fs::BLOCK_SIZE;
+use lazy_static::*;
+use crate::config::{PAGE_SIZE, SWAP_PAGES, SWAP_START_BLOCK};
+use crate::drivers::BLOCK_DEVICE;
+use crate::sync::UPSafeCell;
+use super::address::PhysPageNum;
+
+const BLOCKS_PER_PAGE: usize = PAGE_SIZE / BLOCK_SIZE;
+
+/// A page-sized slot in the swap area of the block device,
+/// it is released when dropped.
+pub struct SwapSlot(usize);
+
+impl SwapSlot {
+    fn blocks(&self) -> impl Iterator<Item = usize> {
+        let start = SWAP_START_BLOCK + self.0 * BLOCKS_PER_PAGE;
+        start..start + BLOCKS_PER_PAGE
+    }
+
+    pub fn write(&self, ppn: PhysPageNum) {
+        let bytes = ppn.get_bytes_array();
+        for (block_id, buf) in self.blocks().zip(bytes.chunks(BLOCK_SIZE)) {
+            BLOCK_DEVICE.write_block(block_id, buf);
+        }
+    }
+
+    pub fn read(&self, ppn: PhysPageNum) {
+        let bytes = ppn.get_bytes_array();
+        for (block_id, buf) in self.blocks().zip(bytes.chunks_mut(BLOCK_SIZE)) {
+            BLOCK_DEVICE.read_block(block_id, buf);
+        }
+    }
+}
+
+impl Drop for SwapSlot {
+    fn drop(&mut self) {
+        SWAP_ALLOCATOR.exclusive_access().dealloc(self.0);
+    }
+}
+
+struct SwapAllocator {
+    current: usize,
+    recycled: Vec<usize>,
+}
+
+impl SwapAllocator {
+    fn alloc(&mut self) -> Option<usize> {
+        if let Some(slot) = self.recycled.pop() {
+            Some(slot)
+        } else if self.current == SWAP_PAGES {
+            None
+        } else {
+            self.current += 1;
+            Some(self.current - 1)
+        }
+    }
+
+    fn dealloc(&mut self, slot: usize) {
+        assert!(slot < self.current, "swap slot {} has not been allocated!", slot);
+        self.recycled.push(slot);
+    }
+}
+
+lazy_static! {
+    static ref SWAP_ALLOCATOR: UPSafeCell<SwapAllocator> = unsafe {
+        UPSafeCell::new(SwapAllocator {
+            current: 0,
+            recycled: Vec::new(),
+        })
+    };
+}
+
+pub fn swap_alloc() -> Option<SwapSlot> {
+    SWAP_ALLOCATOR.exclusive_access().alloc().map(SwapSlot)
+}
@@ -19,8 +19,9 @@ pub fn console_putchar(c: u8) {
     UART.exclusive_access().send(c);
 }
 
+/// 0 if no input is available
 pub fn console_getchar() -> u8 {
-    UART.exclusive_access().recv()
+    UART.exclusive_access().try_recv().unwrap_or(0)
 }
 
 pub fn shutdown(failure: bool) -> ! {
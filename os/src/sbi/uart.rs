@@ -112,6 +112,15 @@ impl UartRaw {
         self.write_port.thr.store(byte, Ordering::Release);
     }
 
+    /// None if no input is available, so that a hart waiting for input does not hold the uart.
+    pub fn try_recv(&self) -> Option<u8> {
+        if self.read_port.lsr.load(Ordering::Acquire) & LineStatus::INPUT_AVAILABLE.bits != 0 {
+            Some(self.read_port.rbr.load(Ordering::Acquire))
+        } else {
+            None
+        }
+    }
+
     pub fn recv(&self) -> u8 {
         wait_for!((self.read_port.lsr.load(Ordering::Acquire) & LineStatus::INPUT_AVAILABLE.bits) != 0);
         self.read_port.rbr.load(Ordering::Acquire)
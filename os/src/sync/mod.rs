@@ -1,5 +1,7 @@
 mod up;
 mod mutex;
+mod spin_lock;
 
 pub use up::UPSafeCell;
+pub use spin_lock::SpinLock;
 pub use mutex::{Mutex, MutexSpin, MutexBlocking};
\ No newline at end of file
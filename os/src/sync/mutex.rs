@@ -2,7 +2,7 @@ use crate::sync::UPSafeCell;
 use crate::task::{TaskControlBlock, suspend_current_and_run_next, current_task, block_current_and_run_next, wakeup_task};
 use alloc::collections::VecDeque;
 use alloc::sync::Arc;
-use core::cell::RefMut;
+use spin::MutexGuard;
 
 pub trait Mutex: Sync + Send {
     fn lock(&self);
@@ -14,7 +14,7 @@ pub struct MutexSpin {
 }
 
 impl MutexSpin {
-    pub fn locked_exclusive_access(&self) -> RefMut<'_, bool> {
+    pub fn locked_exclusive_access(&self) -> MutexGuard<'_, bool> {
         self.locked.exclusive_access()
     }
 
@@ -56,7 +56,7 @@ pub struct MutexBlockingInner {
 }
 
 impl MutexBlocking {
-    pub fn inner_exclusive_access(&self) -> RefMut<'_, MutexBlockingInner> {
+    pub fn inner_exclusive_access(&self) -> MutexGuard<'_, MutexBlockingInner> {
         self.inner.exclusive_access()
     }
 
@@ -0,0 +1,30 @@
+use core::hint::spin_loop;
+use spin::{Mutex, MutexGuard};
+use crate::ipi::handle_calls;
+
+/// A spinlock that answers IPIs while it spins, so a hart holding it may wait
+/// for the others, say to shoot down their TLB entries.
+pub struct SpinLock<T> {
+    inner: Mutex<T>,
+}
+
+impl<T> SpinLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self { inner: Mutex::new(value) }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.inner.try_lock() {
+                return guard;
+            }
+            handle_calls();
+            spin_loop();
+        }
+    }
+
+    /// Return None at once if another hart holds it.
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        self.inner.try_lock()
+    }
+}
@@ -1,18 +1,24 @@
-use core::cell::{RefCell, RefMut};
+use spin::MutexGuard;
+use super::SpinLock;
+
+/// Exclusive access to the inner data behind a spinlock, so it is also safe
+/// while several harts run. Accessing it again on the same hart deadlocks.
 pub struct UPSafeCell<T> {
     /// inner data
-    inner: RefCell<T>,
+    inner: SpinLock<T>,
 }
 
-unsafe impl<T> Sync for UPSafeCell<T> {}
-
 impl<T> UPSafeCell<T> {
-    /// User is responsible to guarantee that inner struct is only used in uniprocessor.
+    /// The unsafe constructor is kept from when it was only safe on a uniprocessor.
     pub unsafe fn new(value: T) -> Self {
-        Self { inner: RefCell::new(value) }
+        Self { inner: SpinLock::new(value) }
     }
-    /// Panic if the data has been borrowed.
-    pub fn exclusive_access(&self) -> RefMut<'_, T> {
-        self.inner.borrow_mut()
+    /// Spin while another hart accesses the data, answering IPIs meanwhile.
+    pub fn exclusive_access(&self) -> MutexGuard<'_, T> {
+        self.inner.lock()
     }
-}
\ No newline at end of file
+    /// Return None if it is accessed already, on this hart as well.
+    pub fn try_exclusive_access(&self) -> Option<MutexGuard<'_, T>> {
+        self.inner.try_lock()
+    }
+}
@@ -21,6 +21,9 @@ pub fn sys_open(path: *const u8, flags: u32) -> isize {
     let task = current_task().unwrap();
     let process = task.get_process();
     let token = current_user_token();
+    if !process.inner_exclusive_access().memory_set.prepare_user_str(path as usize) {
+        return -1;
+    }
     let path = translated_str(token, path);
     if let Some(inode) = open_file(
         path.as_str(),
@@ -54,6 +57,9 @@ pub fn sys_pipe(pipe: *mut usize) -> isize {
     let process = task.get_process();
     let token = current_user_token();
     let mut process_inner = process.inner_exclusive_access();
+    if !process_inner.memory_set.prepare_user_access(pipe as usize, 2 * size_of::<usize>(), true) {
+        return -1;
+    }
     let (pipe_read, pipe_write) = make_pipe();
     let read_fd = process_inner.alloc_fd();
     process_inner.fd_table[read_fd] = Some(pipe_read);
@@ -68,17 +74,21 @@ pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
     let token = current_user_token();
     let task = current_task().unwrap();
     let process = task.get_process();
-    let process_inner = process.inner_exclusive_access();
+    let mut process_inner = process.inner_exclusive_access();
     if fd >= process_inner.fd_table.len() {
         return -1;
     }
     if let Some(file) = &process_inner.fd_table[fd] {
         let file = file.clone();
+        if !process_inner.memory_set.prepare_user_access(buf as usize, len, true) {
+            return -1;
+        }
+        // the frames stay allocated if the file blocks and the pages are unmapped meanwhile
+        let frames = process_inner.memory_set.pin_user_pages(buf as usize, len);
+        let buffer = UserBuffer::new(translated_byte_buffer(token, buf, len), frames);
         // release current task TCB manually to avoid multi-borrow
         drop(process_inner);
-        file.read(
-            UserBuffer::new(translated_byte_buffer(token, buf, len))
-        ) as isize
+        file.read(buffer) as isize
     } else {
         -1
     }
@@ -88,17 +98,21 @@ pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
     let token = current_user_token();
     let task = current_task().unwrap();
     let process = task.get_process();
-    let process_inner = process.inner_exclusive_access();
+    let mut process_inner = process.inner_exclusive_access();
     if fd >= process_inner.fd_table.len() {
         return -1;
     }
     if let Some(file) = &process_inner.fd_table[fd] {
         let file = file.clone();
+        if !process_inner.memory_set.prepare_user_access(buf as usize, len, false) {
+            return -1;
+        }
+        // the frames stay allocated if the file blocks and the pages are unmapped meanwhile
+        let frames = process_inner.memory_set.pin_user_pages(buf as usize, len);
+        let buffer = UserBuffer::new(translated_byte_buffer(token, buf, len), frames);
         // release current task TCB manually to avoid multi-borrow
         drop(process_inner);
-        file.write(
-            UserBuffer::new(translated_byte_buffer(token, buf, len))
-        ) as isize
+        file.write(buffer) as isize
     } else {
         -1
     }
@@ -0,0 +1,192 @@
+use alloc::sync::Arc;
+use crate::config::{PAGE_SIZE, USER_SPACE_END};
+use crate::mm::{MapPermission, SHM_MANAGER};
+use crate::task::current_process;
+
+/// prot: bit 0 readable, bit 1 writable, bit 2 executable
+fn prot_to_permission(prot: usize) -> Option<MapPermission> {
+    if prot & !0x7 != 0 || prot & 0x7 == 0 {
+        return None;
+    }
+    Some(MapPermission::from_bits((prot << 1) as u8).unwrap() | MapPermission::U)
+}
+
+fn check_user_range(start: usize, len: usize) -> bool {
+    start % PAGE_SIZE == 0
+        && len > 0
+        && start.checked_add(len).is_some_and(|end| end <= USER_SPACE_END)
+}
+
+/// Move the program break to `addr`, return the new break,
+/// or the current one if `addr` is 0 or the heap can not be moved there.
+pub fn sys_brk(addr: usize) -> isize {
+    let process = current_process();
+    if addr != 0 {
+        process.change_program_brk(addr);
+    }
+    process.inner_exclusive_access().program_brk as isize
+}
+
+bitflags! {
+    pub struct MmapFlags: u32 {
+        const SHARED = 0x01;
+        const PRIVATE = 0x02;
+        const ANONYMOUS = 0x20;
+        const HUGETLB = 0x40000;
+    }
+}
+
+/// Map anonymous memory or the file `fd` from `offset`,
+/// let the kernel choose the address if `start` is 0.
+/// Return the start address of the mapping.
+pub fn sys_mmap(start: usize, len: usize, prot: usize, flags: u32, fd: usize, offset: usize) -> isize {
+    if !check_user_range(start, len) {
+        return -1;
+    }
+    let (Some(permission), Some(flags)) = (prot_to_permission(prot), MmapFlags::from_bits(flags)) else {
+        return -1;
+    };
+    let shared = flags.contains(MmapFlags::SHARED);
+    if shared == flags.contains(MmapFlags::PRIVATE) {
+        return -1;
+    }
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    if flags.contains(MmapFlags::ANONYMOUS) {
+        // anonymous memory shared with children is not supported yet
+        if shared {
+            return -1;
+        }
+        let memory_set = &mut process_inner.memory_set;
+        let start = if flags.contains(MmapFlags::HUGETLB) {
+            memory_set.mmap_huge(start, len, permission)
+        } else {
+            memory_set.mmap(start, len, permission)
+        };
+        return start.map_or(-1, |start| start as isize);
+    }
+    // files are mapped in normal pages only
+    if flags.contains(MmapFlags::HUGETLB) {
+        return -1;
+    }
+    if offset % PAGE_SIZE != 0 || fd >= process_inner.fd_table.len() {
+        return -1;
+    }
+    let Some(file) = process_inner.fd_table[fd].clone() else {
+        return -1;
+    };
+    let Some(inode) = file.inode() else {
+        return -1;
+    };
+    if !file.readable() || (shared && permission.contains(MapPermission::W) && !file.writable()) {
+        return -1;
+    }
+    // nor can mprotect make it writable later
+    let mut max_permission = MapPermission::all();
+    if shared && !file.writable() {
+        max_permission -= MapPermission::W;
+    }
+    process_inner
+        .memory_set
+        .mmap_file(start, len, permission, max_permission, inode, offset, shared)
+        .map_or(-1, |start| start as isize)
+}
+
+pub fn sys_munmap(start: usize, len: usize) -> isize {
+    if !check_user_range(start, len) {
+        return -1;
+    }
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    if process_inner.memory_set.munmap(start, len) {
+        0
+    } else {
+        -1
+    }
+}
+
+pub fn sys_mprotect(start: usize, len: usize, prot: usize) -> isize {
+    if !check_user_range(start, len) {
+        return -1;
+    }
+    if let Some(permission) = prot_to_permission(prot) {
+        let process = current_process();
+        let mut process_inner = process.inner_exclusive_access();
+        if process_inner.memory_set.mprotect(start, len, permission) {
+            0
+        } else {
+            -1
+        }
+    } else {
+        -1
+    }
+}
+
+/// Write dirty pages of shared file mappings in [start, start + len) back to the files.
+pub fn sys_msync(start: usize, len: usize) -> isize {
+    if !check_user_range(start, len) {
+        return -1;
+    }
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    if process_inner.memory_set.msync(start, len) {
+        0
+    } else {
+        -1
+    }
+}
+
+/// attach a shared memory segment read-only
+const SHM_RDONLY: usize = 0o10000;
+/// remove a shared memory segment
+const IPC_RMID: usize = 0;
+
+/// Return the id of the shared memory segment with `key`, creating it if it does not exist.
+pub fn sys_shmget(key: usize, size: usize) -> isize {
+    SHM_MANAGER
+        .exclusive_access()
+        .get(key, size)
+        .map_or(-1, |id| id as isize)
+}
+
+/// Attach the segment `shmid`, let the kernel choose the address if `start` is 0.
+/// Return the start address of the attachment.
+pub fn sys_shmat(shmid: usize, start: usize, shmflg: usize) -> isize {
+    if start % PAGE_SIZE != 0 || shmflg & !SHM_RDONLY != 0 {
+        return -1;
+    }
+    let Some(segment) = SHM_MANAGER.exclusive_access().segment(shmid) else {
+        return -1;
+    };
+    let mut permission = MapPermission::R | MapPermission::U;
+    if shmflg & SHM_RDONLY == 0 {
+        permission |= MapPermission::W;
+    }
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    let Some(start) = process_inner.memory_set.attach_shm(start, permission, Arc::clone(&segment)) else {
+        return -1;
+    };
+    drop(process_inner);
+    SHM_MANAGER.exclusive_access().set_attached(shmid, &segment);
+    start as isize
+}
+
+pub fn sys_shmdt(start: usize) -> isize {
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    if process_inner.memory_set.detach_shm(start) {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Only IPC_RMID is supported, the frames of an attached segment are released after the last detach.
+pub fn sys_shmctl(shmid: usize, cmd: usize) -> isize {
+    if cmd == IPC_RMID && SHM_MANAGER.exclusive_access().remove(shmid) {
+        0
+    } else {
+        -1
+    }
+}
@@ -1,9 +1,9 @@
 use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
-use crate::fs::{open_file, OpenFlags};
+use crate::fs::{open_file, File, OpenFlags};
 use crate::mm::{translated_ref, translated_refmut, translated_str};
-use crate::task::{suspend_current_and_run_next, exit_current_and_run_next, current_task, add_task, current_user_token, SignalFlags, SignalAction, MAX_SIG, pid2process};
+use crate::task::{suspend_current_and_run_next, exit_current_and_run_next, current_task, current_process, add_task, current_user_token, SignalFlags, SignalAction, MAX_SIG, MAX_PRIORITY, MIN_PRIORITY, pid2process};
 use crate::timer::get_time_ms;
 
 pub fn sys_exit(exit_code: i32) -> ! {
@@ -63,6 +63,11 @@ pub fn sys_sigaction(
             return -1;
         }
         let prev_action = process_inner.signal_actions.table[signum as usize];
+        if !process_inner.memory_set.prepare_user_access(action as usize, size_of::<SignalAction>(), false)
+            || !process_inner.memory_set.prepare_user_access(old_action as usize, size_of::<SignalAction>(), true)
+        {
+            return -1;
+        }
         *translated_refmut(token, old_action) = prev_action;
         process_inner.signal_actions.table[signum as usize] = *translated_ref(token, action);
         0
@@ -105,13 +110,25 @@ pub fn sys_get_time() -> isize {
     get_time_ms() as isize
 }
 
+/// Return the new priority of the current thread, or -1 if it is out of
+/// [`MIN_PRIORITY`, `MAX_PRIORITY`]
+pub fn sys_set_priority(prio: isize) -> isize {
+    if prio < MIN_PRIORITY as isize || prio > MAX_PRIORITY as isize {
+        return -1;
+    }
+    current_task().unwrap().inner_exclusive_access().priority = prio as usize;
+    prio
+}
+
 pub fn sys_getpid() -> isize {
     current_task().unwrap().get_process().getpid() as isize
 }
 
 pub fn sys_fork() -> isize {
     let current_task = current_task().unwrap();
-    let new_process = current_task.get_process().fork();
+    let Some(new_process) = current_task.get_process().fork() else {
+        return -1;
+    };
     let new_process_inner = new_process.inner_exclusive_access();
     let new_pid = new_process.getpid();
     let new_main_thread = new_process_inner
@@ -129,22 +146,38 @@ pub fn sys_fork() -> isize {
 
 pub fn sys_exec(path: *const u8, mut args: *const usize) -> isize {
     let token = current_user_token();
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    if !process_inner.memory_set.prepare_user_str(path as usize) {
+        return -1;
+    }
     let path = translated_str(token, path);
     let mut args_vec: Vec<String> = Vec::new();
     loop {
+        if !process_inner.memory_set.prepare_user_access(args as usize, size_of::<usize>(), false) {
+            return -1;
+        }
         let arg_str_ptr = *translated_ref(token, args);
         if arg_str_ptr == 0 {
             break;
         }
+        if !process_inner.memory_set.prepare_user_str(arg_str_ptr) {
+            return -1;
+        }
         args_vec.push(translated_str(token, arg_str_ptr as *const u8));
         unsafe { args = args.add(1); }
     }
+    drop(process_inner);
+    drop(process);
     if let Some(app_inode) = open_file(path.as_str(), OpenFlags::RDONLY) {
         let all_data = app_inode.read_all();
         let task = current_task().unwrap();
         let argc = args_vec.len();
-        task.get_process().exec(all_data.as_slice(), args_vec);
-        argc as isize
+        if task.get_process().exec(all_data.as_slice(), app_inode.inode().unwrap(), args_vec) {
+            argc as isize
+        } else {
+            -1
+        }
     } else {
         -1
     }
@@ -167,7 +200,10 @@ pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
         .iter()
         .enumerate()
         .find(|(_, p)| {
-            p.inner_exclusive_access().is_zombie && (pid == -1 || pid as usize == p.getpid())
+            // the exiting thread may still hold it on another hart
+            p.inner_exclusive_access().is_zombie
+                && Arc::strong_count(p) == 1
+                && (pid == -1 || pid as usize == p.getpid())
         });
     if let Some((idx, _)) = pair {
         let child = process_inner.children.remove(idx);
@@ -175,7 +211,10 @@ pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
         assert_eq!(Arc::strong_count(&child), 1);
         let found_pid = child.getpid();
         let exit_code = child.inner_exclusive_access().exit_code;
-        *translated_refmut(process_inner.memory_set.token(), exit_code_ptr) = exit_code;
+        // the child is reaped anyway, only its exit code is lost
+        if process_inner.memory_set.prepare_user_access(exit_code_ptr as usize, size_of::<i32>(), true) {
+            *translated_refmut(process_inner.memory_set.token(), exit_code_ptr) = exit_code;
+        }
         found_pid as isize
     } else {
         -2
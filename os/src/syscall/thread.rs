@@ -7,17 +7,24 @@ pub fn sys_thread_create(entry: usize, arg: usize) -> isize {
     let task = current_task().unwrap();
     let process = task.get_process();
     // create a new thread
-    let new_task = Arc::new(TaskControlBlock::new(
+    let Some(new_task) = TaskControlBlock::new(
         Arc::clone(&process),
         task.inner_exclusive_access().res.as_ref().unwrap().ustack_base,
         true,
-    ));
-    // add new task to scheduler
-    add_task(Arc::clone(&new_task));
+    ) else {
+        return -1;
+    };
+    let new_task = Arc::new(new_task);
     let new_task_inner = new_task.inner_exclusive_access();
     let new_task_res = new_task_inner.res.as_ref().unwrap();
     let new_task_tid = new_task_res.tid;
     let mut process_inner = process.inner_exclusive_access();
+    // the main thread is exiting on another hart, the new thread is freed with its resources
+    if process_inner.is_zombie {
+        drop(process_inner);
+        drop(new_task_inner);
+        return -1;
+    }
     // add new thread to current process
     let tasks = &mut process_inner.tasks;
     while tasks.len() < new_task_tid + 1 {
@@ -33,6 +40,10 @@ pub fn sys_thread_create(entry: usize, arg: usize) -> isize {
         trap_handler as usize,
     );
     (*new_task_trap_cx).x[10] = arg;
+    drop(new_task_inner);
+    drop(process_inner);
+    // add new task to scheduler once its trap context is ready for any hart to run it
+    add_task(new_task);
     new_task_tid as isize
 }
 
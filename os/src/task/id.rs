@@ -1,8 +1,8 @@
 use alloc::sync::{Weak, Arc};
 use alloc::vec::Vec;
 use lazy_static::lazy_static;
-use crate::config::{KERNEL_STACK_SIZE, PAGE_SIZE, TRAMPOLINE, TRAP_CONTEXT_BASE, USER_STACK_SIZE};
-use crate::mm::{MapPermission, PhysPageNum, VirtAddr, KERNEL_SPACE};
+use crate::config::{KERNEL_STACK_SIZE, PAGE_SIZE, TRAMPOLINE, TRAP_CONTEXT_BASE, USER_STACK_LIMIT, USER_STACK_SIZE};
+use crate::mm::{MapPermission, MemorySet, PhysPageNum, VirtAddr, KERNEL_SPACE};
 use crate::sync::UPSafeCell;
 use crate::task::process::ProcessControlBlock;
 
@@ -71,21 +71,23 @@ pub fn kernel_stack_position(kstack_id: usize) -> (usize, usize) {
     (bottom, top)
 }
 
-pub fn kstack_alloc() -> KernelStack {
-    let kstack_id = KSTACK_ALLOCATOR.exclusive_access().alloc();
-    let (kstack_bottom, kstack_top) = kernel_stack_position(kstack_id);
+/// Return None if out of memory
+pub fn kstack_alloc() -> Option<KernelStack> {
+    // the id is given back by drop if mapping fails
+    let kstack = KernelStack(KSTACK_ALLOCATOR.exclusive_access().alloc());
+    let (kstack_bottom, kstack_top) = kernel_stack_position(kstack.0);
     KERNEL_SPACE
-        .exclusive_access()
+        .lock()
         .insert_framed_area(
             kstack_bottom.into(),
             kstack_top.into(),
             MapPermission::R | MapPermission::W,
-        );
-    KernelStack(kstack_id)
+        )?;
+    Some(kstack)
 }
 
 impl KernelStack {
-    pub fn new() -> Self {
+    pub fn new() -> Option<Self> {
         kstack_alloc()
     }
     
@@ -107,7 +109,7 @@ impl Drop for KernelStack {
         let (kernel_stack_bottom, _) = kernel_stack_position(self.0);
         let kernel_stack_bottom_va : VirtAddr = kernel_stack_bottom.into();
         KERNEL_SPACE
-            .exclusive_access()
+            .lock()
             .remove_area_with_start_vpn(kernel_stack_bottom_va.into());
         KSTACK_ALLOCATOR.exclusive_access().dealloc(self.0);
     }
@@ -117,8 +119,13 @@ fn trap_cx_bottom_from_tid(tid: usize) -> usize {
     TRAP_CONTEXT_BASE - tid * PAGE_SIZE
 }
 
+/// Lowest address a user stack may grow to, the page below it is an unmapped guard page.
 fn ustack_bottom_from_tid(ustack_base: usize, tid: usize) -> usize {
-    ustack_base + tid * (PAGE_SIZE + USER_STACK_SIZE)
+    ustack_base + tid * (PAGE_SIZE + USER_STACK_LIMIT) + PAGE_SIZE
+}
+
+pub fn ustack_top_from_tid(ustack_base: usize, tid: usize) -> usize {
+    ustack_bottom_from_tid(ustack_base, tid) + USER_STACK_LIMIT
 }
 
 pub struct TaskUserRes {
@@ -128,52 +135,64 @@ pub struct TaskUserRes {
 }
 
 impl TaskUserRes {
+    /// Return None if out of memory
     pub fn new(
         process: Arc<ProcessControlBlock>,
         ustack_base: usize,
         alloc_user_res: bool,
-    ) -> Self {
+    ) -> Option<Self> {
         let tid = process.inner_exclusive_access().alloc_tid();
         let task_user_res = Self {
             tid,
             ustack_base,
             process: Arc::downgrade(&process),
         };
-        if alloc_user_res {
-            task_user_res.alloc_user_res();
+        if alloc_user_res && task_user_res.alloc_user_res().is_none() {
+            // drop unmaps what has been mapped, but the tid is given back here
+            drop(task_user_res);
+            process.inner_exclusive_access().dealloc_tid(tid);
+            return None;
         }
-        task_user_res
+        Some(task_user_res)
     }
     
-    pub fn alloc_user_res(&self) {
+    /// Return None if out of memory
+    pub fn alloc_user_res(&self) -> Option<()> {
         let process = self.process.upgrade().unwrap();
         let mut process_inner = process.inner_exclusive_access();
-        // alloc user stack
-        let ustack_bottom = ustack_bottom_from_tid(self.ustack_base, self.tid);
-        let ustack_top = ustack_bottom + USER_STACK_SIZE;
-        process_inner.memory_set.insert_framed_area(
-            ustack_bottom.into(),
+        self.alloc_user_res_in(&mut process_inner.memory_set, self.ustack_base)
+    }
+
+    /// Map the user stack from `ustack_base` and the trap context into `memory_set`,
+    /// which is not yet the one of the process when it execs.
+    pub fn alloc_user_res_in(&self, memory_set: &mut MemorySet, ustack_base: usize) -> Option<()> {
+        // alloc user stack, which grows on page faults
+        let ustack_bottom = ustack_bottom_from_tid(ustack_base, self.tid);
+        let ustack_top = ustack_top_from_tid(ustack_base, self.tid);
+        memory_set.insert_stack_area(
+            (ustack_top - USER_STACK_SIZE).into(),
             ustack_top.into(),
+            ustack_bottom.into(),
             MapPermission::R | MapPermission::W | MapPermission::U,
         );
         // alloc trap_cx
         let trap_cx_bottom = trap_cx_bottom_from_tid(self.tid);
         let trap_cx_top = trap_cx_bottom + PAGE_SIZE;
-        process_inner.memory_set.insert_framed_area(
+        memory_set.insert_framed_area(
             trap_cx_bottom.into(),
             trap_cx_top.into(),
             MapPermission::R | MapPermission::W,
-        );
+        )
     }
     
     fn dealloc_user_res(&self) {
         let process = self.process.upgrade().unwrap();
         let mut process_inner = process.inner_exclusive_access();
-        // dealloc ustack manually
-        let ustack_bottom_va: VirtAddr = ustack_bottom_from_tid(self.ustack_base, self.tid).into();
+        // dealloc ustack manually, its start has moved if it has grown
+        let ustack_top_va: VirtAddr = (self.ustack_top() - 1).into();
         process_inner
             .memory_set
-            .remove_area_with_start_vpn(ustack_bottom_va.into());
+            .remove_area_containing(ustack_top_va.floor());
         // dealloc trap_cx manually
         let trap_cx_bottom_va: VirtAddr = trap_cx_bottom_from_tid(self.tid).into();
         process_inner
@@ -201,7 +220,7 @@ impl TaskUserRes {
     }
     
     pub fn ustack_top(&self) -> usize {
-        ustack_bottom_from_tid(self.ustack_base, self.tid) + USER_STACK_SIZE
+        ustack_top_from_tid(self.ustack_base, self.tid)
     }
 }
 
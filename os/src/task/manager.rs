@@ -1,78 +1,132 @@
-use alloc::collections::{BTreeMap, VecDeque};
+use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use lazy_static::*;
-use crate::sync::UPSafeCell;
+use crate::sync::SpinLock;
+use crate::config::INIT_PROC;
+use crate::ipi::{handle_calls, kick_idle_hart};
+use crate::mm::zero_page_table;
 use crate::task::process::ProcessControlBlock;
-use crate::task::{TaskControlBlock, TaskStatus};
-
-pub struct TaskManager {
-    ready_queue: VecDeque<Arc<TaskControlBlock>>,
-}
-
-/// A simple FIFO scheduler
-impl TaskManager {
-    pub fn new() -> Self {
-        Self { ready_queue: VecDeque::new(), }
-    }
-
-    pub fn add(&mut self, task: Arc<TaskControlBlock>) {
-        self.ready_queue.push_back(task);
-    }
-
-    pub fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
-        self.ready_queue.pop_front()
-    }
-    
-    pub fn remove(&mut self, task: Arc<TaskControlBlock>) {
-        if let Some((id, _)) = self
-            .ready_queue
-            .iter()
-            .enumerate()
-            .find(|(_, t)| Arc::ptr_eq(t, &task)) {
-            self.ready_queue.remove(id);
-        }
-    }
-}
+use crate::task::scheduler::{Scheduler, TaskManager};
+use crate::task::{hart_id, SignalFlags, TaskControlBlock, TaskStatus};
 
 lazy_static! {
-    pub static ref TASK_MANAGER: UPSafeCell<TaskManager> = unsafe {
-        UPSafeCell::new(TaskManager::new())
-    };
-    pub static ref PID2PCB: UPSafeCell<BTreeMap<usize, Arc<ProcessControlBlock>>> = unsafe {
-        UPSafeCell::new(BTreeMap::new())
-    };
+    pub static ref TASK_MANAGER: SpinLock<TaskManager> = SpinLock::new(TaskManager::new());
+    pub static ref PID2PCB: SpinLock<BTreeMap<usize, Arc<ProcessControlBlock>>> = SpinLock::new(BTreeMap::new());
 }
 
 pub fn add_task(task: Arc<TaskControlBlock>) {
-    TASK_MANAGER.exclusive_access().add(task);
+    TASK_MANAGER.lock().add(task);
+    kick_idle_hart();
 }
 
 pub fn wakeup_task(task: Arc<TaskControlBlock>) {
     let mut task_inner = task.inner_exclusive_access();
     task_inner.task_status = TaskStatus::Ready;
     drop(task_inner);
-    add_task(task);
+    TASK_MANAGER.lock().add_woken(task);
+    kick_idle_hart();
+}
+
+pub fn add_preempted_task(task: Arc<TaskControlBlock>) {
+    TASK_MANAGER.lock().add_preempted(task);
+}
+
+pub fn charge_task(task: &TaskControlBlock, time: usize) {
+    TASK_MANAGER.lock().charge(task, time);
 }
 
 pub fn fetch_task() -> Option<Arc<TaskControlBlock>> {
-    TASK_MANAGER.exclusive_access().fetch()
+    TASK_MANAGER.lock().fetch()
 }
 
 pub fn remove_task(task: Arc<TaskControlBlock>) {
-    TASK_MANAGER.exclusive_access().remove(task);
+    TASK_MANAGER.lock().remove(task);
 }
 
 pub fn pid2process(pid: usize) -> Option<Arc<ProcessControlBlock>> {
-    let map = PID2PCB.exclusive_access();
+    let map = PID2PCB.lock();
     map.get(&pid).map(Arc::clone)
 }
 
+/// All processes, PID2PCB is not held while they are accessed since a fork
+/// holds the parent while it inserts the child.
+fn processes() -> Vec<Arc<ProcessControlBlock>> {
+    PID2PCB.lock().values().cloned().collect()
+}
+
+/// The process holding the most user frames except initproc and those already
+/// killed, with the number of them.
+pub fn select_oom_victim() -> Option<(Arc<ProcessControlBlock>, usize)> {
+    processes()
+        .into_iter()
+        .filter(|process| process.getpid() != INIT_PROC)
+        .filter_map(|process| {
+            let process_inner = process.inner_exclusive_access();
+            if process_inner.signals.contains(SignalFlags::SIGKILL) {
+                return None;
+            }
+            let pages = process_inner.memory_set.resident_pages();
+            drop(process_inner);
+            Some((process, pages))
+        })
+        .max_by_key(|(_, pages)| *pages)
+}
+
+/// pid of the process a frame was reclaimed from last, the ones after it go first next time
+static RECLAIM_HAND: AtomicUsize = AtomicUsize::new(0);
+/// the hart reclaiming plus 1, or 0, one hart reclaims at a time
+static RECLAIMER: AtomicUsize = AtomicUsize::new(0);
+
+/// Swap out a page of any process for an allocation that has run out of frames,
+/// going round the processes so they give up pages in turn. Processes accessed on
+/// other harts or by the allocating code itself are skipped. Return false if none
+/// could be swapped out.
+pub fn reclaim_frame() -> bool {
+    let this = hart_id() + 1;
+    if let Err(reclaimer) = RECLAIMER.compare_exchange(0, this, Ordering::Acquire, Ordering::Relaxed) {
+        // another hart is freeing a frame and may wait for this one meanwhile,
+        // but swapping out does not reclaim again
+        handle_calls();
+        return reclaimer != this;
+    }
+    // PID2PCB may be held by the allocating code too
+    let processes: Vec<_> = match PID2PCB.try_lock() {
+        Some(map) => map.values().cloned().collect(),
+        None => Vec::new(),
+    };
+    let hand = RECLAIM_HAND.load(Ordering::Relaxed);
+    let (after, before): (Vec<_>, Vec<_>) = processes
+        .into_iter()
+        .partition(|process| process.getpid() > hand);
+    let victim = after.into_iter().chain(before).find(|process| {
+        process
+            .try_inner_exclusive_access()
+            .is_some_and(|mut process_inner| process_inner.memory_set.swap_out_one())
+    });
+    if let Some(process) = victim.as_ref() {
+        RECLAIM_HAND.store(process.getpid(), Ordering::Relaxed);
+    }
+    RECLAIMER.store(0, Ordering::Release);
+    victim.is_some()
+}
+
+/// Share identical read-only pages across all processes, return the number of frames freed.
+pub fn merge_identical_pages() -> usize {
+    let mut pages = zero_page_table();
+    processes()
+        .iter()
+        .map(|process| process.inner_exclusive_access().memory_set.merge_identical_pages(&mut pages))
+        .sum()
+}
+
 pub fn insert_into_pid2process(pid: usize, process: Arc<ProcessControlBlock>) {
-    PID2PCB.exclusive_access().insert(pid, process);
+    PID2PCB.lock().insert(pid, process);
 }
 
 pub fn remove_from_pid2process(pid: usize) {
-    let mut map = PID2PCB.exclusive_access();
+    let mut map = PID2PCB.lock();
     if map.remove(&pid).is_none() {
         panic!("cannot find pid {} in pid2pcb!", pid);
     }
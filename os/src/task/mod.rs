@@ -7,21 +7,27 @@ mod processor;
 mod action;
 mod signal;
 mod process;
+mod scheduler;
 
 use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::hint::spin_loop;
+use core::sync::atomic::Ordering;
 use lazy_static::*;
 pub use context::TaskContext;
 pub use task::{TaskControlBlock, TaskStatus};
-pub use processor::{run_tasks, schedule, take_current_task, current_task, current_user_token, current_trap_cx, current_process, current_trap_cx_user_va, current_kstack_top};
-pub use manager::{add_task, wakeup_task, pid2process, remove_from_pid2process};
+pub use processor::{hart_id, run_tasks, schedule, take_current_task, current_slice_used, current_task, current_user_token, current_trap_cx, current_process, current_trap_cx_user_va, current_kstack_top};
+pub use manager::{add_task, wakeup_task, pid2process, reclaim_frame, remove_from_pid2process};
 pub use signal::{MAX_SIG, SignalFlags};
+pub use scheduler::{MAX_PRIORITY, MIN_PRIORITY};
 pub use action::{SignalAction, SignalActions};
 use crate::config::INIT_PROC;
-use crate::fs::{open_file, OpenFlags};
+use crate::fs::{open_file, File, OpenFlags};
+use crate::ipi::handle_calls;
+use crate::mm::{frame_stats, MapPermission, PageFault, VirtAddr};
 use crate::sbi::shutdown;
 use crate::task::id::TaskUserRes;
-use crate::task::manager::remove_task;
+use crate::task::manager::{add_preempted_task, merge_identical_pages, remove_task, select_oom_victim};
 use crate::task::process::ProcessControlBlock;
 use crate::timer::remove_timer;
 
@@ -29,7 +35,7 @@ lazy_static! {
     pub static ref INITPROC: Arc<ProcessControlBlock> = {
         let inode = open_file("initproc", OpenFlags::RDONLY).unwrap();
         let v = inode.read_all();
-        ProcessControlBlock::new(v.as_slice())
+        ProcessControlBlock::new(v.as_slice(), inode.inode().unwrap())
     };
 }
 
@@ -38,6 +44,15 @@ pub fn add_initproc() {
 }
 
 pub fn suspend_current_and_run_next() {
+    switch_out_current(add_task);
+}
+
+/// The current task has used up its time slice
+pub fn preempt_current_and_run_next() {
+    switch_out_current(add_preempted_task);
+}
+
+fn switch_out_current(requeue: fn(Arc<TaskControlBlock>)) {
     // There must be an application running
     let task = take_current_task().unwrap();
     // access current TCB exclusively
@@ -48,7 +63,7 @@ pub fn suspend_current_and_run_next() {
     drop(task_inner);
     // stop exclusively accessing current TCB
     // push back to ready queue
-    add_task(task);
+    requeue(task);
     // jump to scheduling cycle
     schedule(task_cx_ptr);
 }
@@ -100,29 +115,26 @@ pub fn exit_current_and_run_next(exit_code: i32) {
         process_inner.is_zombie = true;
         // record exit code of main process
         process_inner.exit_code = exit_code;
-        // do not move to its parent but under initproc
-        {
-            let mut initproc_inner = INITPROC.inner_exclusive_access();
-            for child in process_inner.children.iter() {
-                child.inner_exclusive_access().parent = Some(Arc::downgrade(&INITPROC));
-                initproc_inner.children.push(child.clone());
-            }
-            process_inner.children.clear();
-        }
+        let children = core::mem::take(&mut process_inner.children);
         // deallocate user res (including tid/trap_cx/ustack) of all threads
         // it has to be done before we dealloc the whole memory_set
         // otherwise they will be deallocated twice
+        let tasks: Vec<_> = process_inner.tasks.iter().flatten().cloned().collect();
+        // release process_inner first, threads running on other harts may need it to get off,
+        // and a parent locks itself before its children, so no other process is locked under it
+        drop(process_inner);
+        // do not move to its parent but under initproc
+        for child in children.iter() {
+            child.inner_exclusive_access().parent = Some(Arc::downgrade(&INITPROC));
+        }
+        INITPROC.inner_exclusive_access().children.extend(children);
         let mut recycle_res = Vec::<TaskUserRes>::new();
-        for task in process_inner.tasks.iter().filter(|t| t.is_some()) {
-            let task = task.as_ref().unwrap();
+        for task in tasks {
             remove_inactive_task(Arc::clone(&task));
-            let mut task_inner = task.inner_exclusive_access();
-            if let Some(res) = task_inner.res.take() {
+            if let Some(res) = take_res_off_cpu(&task) {
                 recycle_res.push(res);
             }
         }
-        // release process_inner first to let drop(res) access PCB inner
-        drop(process_inner);
         recycle_res.clear();
         let mut process_inner = process.inner_exclusive_access();
         // deallocate other data in user space
@@ -140,6 +152,21 @@ pub fn exit_current_and_run_next(exit_code: i32) {
     schedule(&mut _unused as *mut _);
 }
 
+/// Take the resources of a thread once no hart runs it, a hart fetching it later skips it.
+/// Return None if it has exited itself.
+fn take_res_off_cpu(task: &TaskControlBlock) -> Option<TaskUserRes> {
+    loop {
+        let mut task_inner = task.inner_exclusive_access();
+        if task_inner.res.is_none() || !task.on_cpu.load(Ordering::Acquire) {
+            return task_inner.res.take();
+        }
+        drop(task_inner);
+        // the hart running it may be waiting for this one
+        handle_calls();
+        spin_loop();
+    }
+}
+
 fn remove_inactive_task(task: Arc<TaskControlBlock>) {
     remove_task(Arc::clone(&task));
     remove_timer(Arc::clone(&task));
@@ -159,6 +186,61 @@ pub fn current_add_signal(signal: SignalFlags) {
     process_inner.signals |= signal;
 }
 
+/// Return false if it is a real fault of the program other than a stack overflow.
+/// If memory runs out, a victim is killed and the faulting instruction is retried.
+pub fn current_handle_page_fault(addr: usize, access: MapPermission) -> bool {
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    let vpn = VirtAddr::from(addr).floor();
+    if process_inner.memory_set.is_spurious_fault(vpn, access) {
+        return true;
+    }
+    match process_inner.memory_set.handle_page_fault(vpn, access.contains(MapPermission::W)) {
+        PageFault::Handled => true,
+        PageFault::Invalid => false,
+        PageFault::OutOfMemory => {
+            drop(process_inner);
+            // the faulting access is retried if sharing pages has freed some frames
+            if merge_identical_pages() == 0 {
+                kill_oom_victim(process);
+            }
+            true
+        }
+        PageFault::StackOverflow => {
+            drop(process_inner);
+            println!("[kernel] Stack overflow in application, bad addr = {:#x}, kernel killed it.", addr);
+            current_add_signal(SignalFlags::SIGSEGV);
+            true
+        }
+    }
+}
+
+/// Kill the process using the most memory that is not killed yet and let it release
+/// its memory before the current one goes on, or kill the current one if it is the
+/// victim itself. If only killed ones are left, wait for them to go.
+fn kill_oom_victim(current: Arc<ProcessControlBlock>) {
+    let stats = frame_stats();
+    println!(
+        "[kernel] Out of memory, {} frames used and {} free of {}.",
+        stats.used, stats.free, stats.total
+    );
+    match select_oom_victim() {
+        Some((victim, _)) if Arc::ptr_eq(&victim, &current) => {
+            println!("[kernel] Out of memory, killed the faulting process {}.", current.getpid());
+            current.inner_exclusive_access().signals |= SignalFlags::SIGKILL;
+        }
+        Some((victim, pages)) => {
+            println!(
+                "[kernel] Out of memory, killed process {} holding {} pages.",
+                victim.getpid(), pages
+            );
+            victim.inner_exclusive_access().signals |= SignalFlags::SIGKILL;
+            suspend_current_and_run_next();
+        }
+        None => suspend_current_and_run_next(),
+    }
+}
+
 fn call_kernel_signal_handler(signal: SignalFlags) {
     let task = current_task().unwrap();
     let process = task.get_process();
@@ -2,12 +2,12 @@ use alloc::string::String;
 use alloc::sync::{Arc, Weak};
 use alloc::vec;
 use alloc::vec::Vec;
-use core::cell::RefMut;
-use crate::config::USER_STACK_SIZE;
+use spin::MutexGuard;
+use easy_fs::Inode;
 use crate::fs::{File, Stderr, Stdin, Stdout};
 use crate::mm::{translated_refmut, MemorySet, KERNEL_SPACE};
 use crate::sync::{Mutex, UPSafeCell};
-use crate::task::id::{pid_alloc, PidHandle, RecycleAllocator};
+use crate::task::id::{pid_alloc, ustack_top_from_tid, PidHandle, RecycleAllocator};
 use crate::task::{add_task, SignalActions, SignalFlags, TaskControlBlock};
 use crate::task::manager::insert_into_pid2process;
 use crate::trap::{trap_handler, TrapContext};
@@ -28,6 +28,8 @@ pub struct ProcessControlBlockInner {
     pub fd_table: Vec<Option<Arc<dyn File + Send + Sync>>>,
     pub mutex_list: Vec<Option<Arc<dyn Mutex>>>,
     pub base_size: usize,
+    pub heap_bottom: usize,
+    pub program_brk: usize,
     pub signals: SignalFlags,
     pub signal_mask: SignalFlags,
     pub signal_actions: SignalActions,
@@ -40,17 +42,33 @@ pub struct ProcessControlBlockInner {
 }
 
 impl ProcessControlBlock {
-    pub fn inner_exclusive_access(&self) -> RefMut<'_, ProcessControlBlockInner> {
+    pub fn inner_exclusive_access(&self) -> MutexGuard<'_, ProcessControlBlockInner> {
         self.inner.exclusive_access()
     }
 
+    pub fn try_inner_exclusive_access(&self) -> Option<MutexGuard<'_, ProcessControlBlockInner>> {
+        self.inner.try_exclusive_access()
+    }
+
     pub fn getpid(&self) -> usize {
         self.pid.0
     }
 
-    pub fn new(elf_data: &[u8]) -> Arc<Self> {
+    /// Move the program break to `new_brk`, return false if the heap can not be moved there.
+    pub fn change_program_brk(&self, new_brk: usize) -> bool {
+        let mut inner = self.inner_exclusive_access();
+        let (heap_bottom, old_brk) = (inner.heap_bottom, inner.program_brk);
+        if inner.memory_set.change_brk(heap_bottom, old_brk, new_brk) {
+            inner.program_brk = new_brk;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn new(elf_data: &[u8], inode: Arc<Inode>) -> Arc<Self> {
         // memory_set with elf program headers/trampoline/trap context/user stack
-        let (memory_set, ustack_base, entry_point) = MemorySet::from_elf(elf_data);
+        let (memory_set, ustack_base, heap_bottom, entry_point) = MemorySet::from_elf(elf_data, inode).unwrap();
         // alloc a pid and a kernel stack in kernel space
         let pid_handle = pid_alloc();
         // push a task context which goes to trap_return to the top of kernel stack
@@ -62,6 +80,8 @@ impl ProcessControlBlock {
                     is_zombie: false,
                     memory_set,
                     base_size: ustack_base,
+                    heap_bottom,
+                    program_brk: heap_bottom,
                     parent: None,
                     children: Vec::new(),
                     exit_code: 0,
@@ -91,7 +111,7 @@ impl ProcessControlBlock {
             Arc::clone(&process),
             ustack_base,
             true,
-        ));
+        ).unwrap());
         let task_inner = task.inner_exclusive_access();
         let trap_cx = task_inner.get_trap_cx();
         let ustack_top = task_inner.res.as_ref().unwrap().ustack_top();
@@ -101,7 +121,7 @@ impl ProcessControlBlock {
         *trap_cx = TrapContext::app_init_context(
             entry_point,
             ustack_top,
-            KERNEL_SPACE.exclusive_access().token(),
+            KERNEL_SPACE.lock().token(),
             kstack_top,
             trap_handler as usize,
         );
@@ -115,15 +135,15 @@ impl ProcessControlBlock {
         process
     }
 
-    /// Only support processes with a single thread
-    pub fn fork(self: &Arc<Self>) -> Arc<Self> {
+    /// Only support processes with a single thread, return None if out of memory.
+    pub fn fork(self: &Arc<Self>) -> Option<Arc<Self>> {
         // access parent PCB exclusively
         let mut parent_inner = self.inner_exclusive_access();
         assert_eq!(parent_inner.thread_count(), 1);
-        // copy user space(include trap context)
+        // share user space copy-on-write (trap context is copied)
         let memory_set = MemorySet::from_existed_user(
-            &parent_inner.memory_set
-        );
+            &mut parent_inner.memory_set
+        )?;
         // alloc a pid and a kernel stack in kernel space
         let pid_handle = pid_alloc();
         // copy fd table
@@ -142,6 +162,8 @@ impl ProcessControlBlock {
                     is_zombie: false,
                     memory_set,
                     base_size: parent_inner.base_size,
+                    heap_bottom: parent_inner.heap_bottom,
+                    program_brk: parent_inner.program_brk,
                     parent: Some(Arc::downgrade(self)),
                     children: Vec::new(),
                     exit_code: 0,
@@ -160,8 +182,6 @@ impl ProcessControlBlock {
                 })
             },
         });
-        // add child
-        parent_inner.children.push(child.clone());
         // create main thread of child process
         let ustack_base = parent_inner
             .get_task(0)
@@ -174,7 +194,9 @@ impl ProcessControlBlock {
             Arc::clone(&child),
             ustack_base,
             false, // alloc a new kstack but do not alloc user res again
-        ));
+        )?);
+        // add child
+        parent_inner.children.push(child.clone());
         // attach task to child process
         let mut child_inner = child.inner_exclusive_access();
         child_inner.tasks.push(Some(Arc::clone(&task)));
@@ -188,25 +210,40 @@ impl ProcessControlBlock {
         // add this thread to scheduler
         add_task(task);
         // return
-        child
+        Some(child)
     }
 
-    pub fn exec(&self, elf_data: &[u8], args: Vec<String>) {
+    /// Return false if out of memory, with the old program left untouched.
+    pub fn exec(&self, elf_data: &[u8], inode: Arc<Inode>, args: Vec<String>) -> bool {
         assert_eq!(self.inner_exclusive_access().thread_count(), 1);
         // memory_set with elf program headers/trampoline/trap context/user stack
-        let (memory_set, ustack_base, entry_point) = MemorySet::from_elf(elf_data);
+        let Some((mut memory_set, ustack_base, heap_bottom, entry_point)) = MemorySet::from_elf(elf_data, inode) else {
+            return false;
+        };
         let new_token = memory_set.token();
-        let mut user_sp = ustack_base + USER_STACK_SIZE;
-        // substitute memory_set 
-        self.inner_exclusive_access().memory_set = memory_set;
-        // alloc resource for main thread again
+        // alloc resource for main thread again in the new memory_set
         let task = self.inner_exclusive_access().get_task(0);
         let mut task_inner = task.inner_exclusive_access();
+        let mut user_sp = ustack_top_from_tid(ustack_base, task_inner.res.as_ref().unwrap().tid);
+        if task_inner.res.as_ref().unwrap().alloc_user_res_in(&mut memory_set, ustack_base).is_none() {
+            return false;
+        }
+        // the user stack is mapped lazily, bring in the pages holding arguments
+        let args_size = (args.len() + 2) * size_of::<usize>()
+            + args.iter().map(|arg| arg.len() + 1).sum::<usize>();
+        if !memory_set.prepare_user_access(user_sp - args_size, args_size, true) {
+            return false;
+        }
+        // nothing can fail from here, substitute memory_set and reset the heap
+        let mut process_inner = self.inner_exclusive_access();
+        process_inner.memory_set = memory_set;
+        process_inner.heap_bottom = heap_bottom;
+        process_inner.program_brk = heap_bottom;
+        // update base_size
+        process_inner.base_size = user_sp;
+        drop(process_inner);
         task_inner.res.as_mut().unwrap().ustack_base = ustack_base;
-        task_inner.res.as_mut().unwrap().alloc_user_res();
         task_inner.trap_cx_ppn = task_inner.res.as_mut().unwrap().trap_cx_ppn();
-        // update base_size
-        self.inner_exclusive_access().base_size = user_sp;
         // push arguments on user stack
         user_sp -= (args.len() + 1) * size_of::<usize>();
         let argv_base = user_sp;
@@ -235,12 +272,13 @@ impl ProcessControlBlock {
         *trap_cx = TrapContext::app_init_context(
             entry_point,
             user_sp,
-            KERNEL_SPACE.exclusive_access().token(),
+            KERNEL_SPACE.lock().token(),
             task.kstack.get_top(),
             trap_handler as usize,
         );
         (*trap_cx).x[10] = args.len(); // actually no need to push argc, since later the return value will overwrite a0
         (*trap_cx).x[11] = argv_base;
+        true
     }
 }
 
@@ -1,15 +1,24 @@
 use alloc::sync::Arc;
+use core::arch::asm;
+use core::hint::spin_loop;
+use core::sync::atomic::Ordering;
 use lazy_static::*;
+use spin::MutexGuard;
+use crate::config::{CLOCK_FREQ, CORE_NUM, TICKS_PER_SEC};
+use crate::ipi::{handle_calls, set_idle, take_reschedule, take_tick};
 use crate::sync::UPSafeCell;
 use crate::task::{TaskContext, TaskControlBlock, TaskStatus};
-use crate::task::manager::fetch_task;
+use crate::task::manager::{charge_task, fetch_task};
 use crate::task::process::ProcessControlBlock;
 use crate::task::switch::__switch;
-use crate::trap::TrapContext;
+use crate::timer::{check_timer, get_time};
+use crate::trap::{clear_pending_tick, TrapContext};
 
 pub struct Processor {
     current: Option<Arc<TaskControlBlock>>,
-    idle_task_cx: TaskContext
+    idle_task_cx: TaskContext,
+    /// when the current task was switched to
+    switched_in_at: usize,
 }
 
 impl Processor {
@@ -17,6 +26,7 @@ impl Processor {
         Self {
             current: None,
             idle_task_cx: TaskContext::zero_init(),
+            switched_in_at: 0,
         }
     }
     
@@ -34,17 +44,40 @@ impl Processor {
 }
 
 lazy_static! {
-    pub static ref PROCESSOR: UPSafeCell<Processor> = unsafe {
-        UPSafeCell::new(Processor::new())
-    };
+    /// one for each hart
+    static ref PROCESSORS: [UPSafeCell<Processor>; CORE_NUM] =
+        core::array::from_fn(|_| unsafe { UPSafeCell::new(Processor::new()) });
 }
 
+/// Id of the running hart, kept in tp
+pub fn hart_id() -> usize {
+    let id;
+    unsafe {
+        asm!("mv {}, tp", out(reg) id);
+    }
+    id
+}
+
+fn local_processor() -> MutexGuard<'static, Processor> {
+    PROCESSORS[hart_id()].exclusive_access()
+}
+
+/// Take the current task off the processor and charge it for the time it has run
 pub fn take_current_task() -> Option<Arc<TaskControlBlock>> {
-    PROCESSOR.exclusive_access().take_current()
+    let mut processor = local_processor();
+    let task = processor.take_current()?;
+    charge_task(&task, get_time() - processor.switched_in_at);
+    Some(task)
+}
+
+/// Whether the current task has run for at least half a time slice, ticks come at
+/// a fixed rate, so one may come right after the task was switched to.
+pub fn current_slice_used() -> bool {
+    get_time() - local_processor().switched_in_at >= CLOCK_FREQ / TICKS_PER_SEC / 2
 }
 
 pub fn current_task() -> Option<Arc<TaskControlBlock>> {
-    PROCESSOR.exclusive_access().current()
+    local_processor().current()
 }
 
 pub fn current_process() -> Arc<ProcessControlBlock> {
@@ -75,16 +108,36 @@ pub fn current_kstack_top() -> usize {
 
 pub fn run_tasks() {
     loop {
-        let mut processor = PROCESSOR.exclusive_access();
+        // set and cleared first, so a task added or an IPI sent after the checks below ends the wait
+        set_idle(true);
+        clear_pending_tick();
+        handle_calls();
+        take_tick();
+        take_reschedule();
+        let mut processor = local_processor();
         if let Some(task) = fetch_task() {
+            set_idle(false);
             let idle_task_cx_ptr = processor.get_idle_task_cx_ptr();
+            // the hart it ran on last may not have saved its context yet
+            while task.on_cpu.load(Ordering::Acquire) {
+                handle_calls();
+                spin_loop();
+            }
             // access coming task TCB exclusively
             let mut task_inner = task.inner_exclusive_access();
+            // its process has exited while it was waiting
+            if task_inner.res.is_none() {
+                continue;
+            }
             let next_task_cx_ptr = &task_inner.task_cx as *const TaskContext;
             task_inner.task_status = TaskStatus::Running;
+            task.on_cpu.store(true, Ordering::Relaxed);
             // stop exclusively accessing coming task TCB manually
             drop(task_inner);
+            // kept until it is switched out, an exiting task is still on its kernel stack
+            let running = Arc::clone(&task);
             processor.current = Some(task);
+            processor.switched_in_at = get_time();
             // stop exclusively accessing processor manually
             drop(processor);
             unsafe {
@@ -93,14 +146,21 @@ pub fn run_tasks() {
                     next_task_cx_ptr,
                 );
             }
+            running.on_cpu.store(false, Ordering::Release);
         } else {
-            println!("no tasks available in run_tasks");
+            drop(processor);
+            // no task traps into the kernel on this hart to check the timers
+            check_timer();
+            // until the next tick or IPI
+            unsafe {
+                asm!("wfi");
+            }
         }
     }
 }
 
 pub fn schedule(switched_task_cx_ptr: *mut TaskContext) {
-    let mut processor = PROCESSOR.exclusive_access();
+    let mut processor = local_processor();
     let idle_task_cx_ptr = processor.get_idle_task_cx_ptr();
     drop(processor);
     unsafe {
@@ -0,0 +1,68 @@
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use super::{Scheduler, DEFAULT_PRIORITY};
+use crate::config::{CLOCK_FREQ, TICKS_PER_SEC};
+use crate::task::TaskControlBlock;
+
+/// How far behind the others a woken task may be put, one time slice
+const WAKEUP_BONUS: u64 = (CLOCK_FREQ / TICKS_PER_SEC) as u64;
+
+/// Scheduling state of a task
+#[derive(Default)]
+pub struct SchedEntity {
+    /// time run so far, scaled down for a high priority
+    vruntime: u64,
+}
+
+/// Completely fair scheduling, the ready task that has run the least runs next,
+/// a task's running time counts less the higher its priority is.
+pub struct CfsScheduler {
+    /// ordered by vruntime, then by the order the tasks were added
+    ready: BTreeMap<(u64, usize), Arc<TaskControlBlock>>,
+    seq: usize,
+    /// vruntime of the task fetched last, it never goes back
+    min_vruntime: u64,
+}
+
+impl CfsScheduler {
+    pub fn new() -> Self {
+        Self { ready: BTreeMap::new(), seq: 0, min_vruntime: 0 }
+    }
+
+    /// Insert `task` with a vruntime of at least `floor`.
+    fn enqueue(&mut self, task: Arc<TaskControlBlock>, floor: u64) {
+        let mut task_inner = task.inner_exclusive_access();
+        // a new or long blocked task does not get the time it missed all at once
+        task_inner.sched.vruntime = task_inner.sched.vruntime.max(floor);
+        let key = (task_inner.sched.vruntime, self.seq);
+        drop(task_inner);
+        self.seq += 1;
+        self.ready.insert(key, task);
+    }
+}
+
+impl Scheduler for CfsScheduler {
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        self.enqueue(task, self.min_vruntime);
+    }
+
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        let ((vruntime, _), task) = self.ready.pop_first()?;
+        self.min_vruntime = self.min_vruntime.max(vruntime);
+        Some(task)
+    }
+
+    fn remove(&mut self, task: Arc<TaskControlBlock>) {
+        self.ready.retain(|_, t| !Arc::ptr_eq(t, &task));
+    }
+
+    fn add_woken(&mut self, task: Arc<TaskControlBlock>) {
+        self.enqueue(task, self.min_vruntime.saturating_sub(WAKEUP_BONUS));
+    }
+
+    fn charge(&mut self, task: &TaskControlBlock, time: usize) {
+        let mut task_inner = task.inner_exclusive_access();
+        // at least 1, so even the highest priority does not keep a task ahead for good
+        task_inner.sched.vruntime += (time * DEFAULT_PRIORITY / task_inner.priority).max(1) as u64;
+    }
+}
@@ -0,0 +1,39 @@
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use super::Scheduler;
+use crate::task::TaskControlBlock;
+
+/// A FIFO scheduler keeps nothing per task
+#[derive(Default)]
+pub struct SchedEntity;
+
+/// A simple FIFO scheduler, priorities are ignored
+pub struct FifoScheduler {
+    ready_queue: VecDeque<Arc<TaskControlBlock>>,
+}
+
+impl FifoScheduler {
+    pub fn new() -> Self {
+        Self { ready_queue: VecDeque::new(), }
+    }
+}
+
+impl Scheduler for FifoScheduler {
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        self.ready_queue.push_back(task);
+    }
+
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        self.ready_queue.pop_front()
+    }
+
+    fn remove(&mut self, task: Arc<TaskControlBlock>) {
+        if let Some((id, _)) = self
+            .ready_queue
+            .iter()
+            .enumerate()
+            .find(|(_, t)| Arc::ptr_eq(t, &task)) {
+            self.ready_queue.remove(id);
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use super::Scheduler;
+use crate::task::TaskControlBlock;
+use crate::timer::get_time_ms;
+
+const LEVELS: usize = 4;
+/// All tasks go back to the top level this often, so none of them starves
+const BOOST_INTERVAL_MS: usize = 1000;
+
+/// Scheduling state of a task
+#[derive(Default)]
+pub struct SchedEntity {
+    level: usize,
+    /// boosts done by the time `level` was set, it is stale after another boost
+    epoch: usize,
+}
+
+/// Multi-level feedback queue, a task using up its time slice drops a level
+/// and one coming back from waiting rises a level, so interactive tasks
+/// run ahead of CPU bound ones.
+pub struct MlfqScheduler {
+    queues: [VecDeque<Arc<TaskControlBlock>>; LEVELS],
+    epoch: usize,
+    last_boost_ms: usize,
+}
+
+impl MlfqScheduler {
+    pub fn new() -> Self {
+        Self {
+            queues: [const { VecDeque::new() }; LEVELS],
+            epoch: 0,
+            last_boost_ms: 0,
+        }
+    }
+
+    fn level_of(&self, sched: &SchedEntity) -> usize {
+        if sched.epoch == self.epoch { sched.level } else { 0 }
+    }
+
+    /// Put `task` on the level `f` picks from its current one.
+    fn enqueue(&mut self, task: Arc<TaskControlBlock>, f: impl FnOnce(usize) -> usize) {
+        let mut task_inner = task.inner_exclusive_access();
+        let level = f(self.level_of(&task_inner.sched));
+        task_inner.sched = SchedEntity { level, epoch: self.epoch };
+        drop(task_inner);
+        self.queues[level].push_back(task);
+    }
+
+    /// Move every ready task to the top level, the others are moved when they are added back.
+    fn boost(&mut self) {
+        self.epoch += 1;
+        let (top, lower) = self.queues.split_first_mut().unwrap();
+        for queue in lower {
+            top.append(queue);
+        }
+    }
+}
+
+impl Scheduler for MlfqScheduler {
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        self.enqueue(task, |level| level);
+    }
+
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        let now = get_time_ms();
+        if now - self.last_boost_ms >= BOOST_INTERVAL_MS {
+            self.boost();
+            self.last_boost_ms = now;
+        }
+        self.queues.iter_mut().find_map(|queue| queue.pop_front())
+    }
+
+    fn remove(&mut self, task: Arc<TaskControlBlock>) {
+        for queue in self.queues.iter_mut() {
+            queue.retain(|t| !Arc::ptr_eq(t, &task));
+        }
+    }
+
+    fn add_preempted(&mut self, task: Arc<TaskControlBlock>) {
+        self.enqueue(task, |level| (level + 1).min(LEVELS - 1));
+    }
+
+    fn add_woken(&mut self, task: Arc<TaskControlBlock>) {
+        self.enqueue(task, |level| level.saturating_sub(1));
+    }
+}
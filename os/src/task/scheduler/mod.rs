@@ -0,0 +1,48 @@
+#[cfg(feature = "sched_cfs")]
+mod cfs;
+#[cfg(feature = "sched_fifo")]
+mod fifo;
+#[cfg(feature = "sched_mlfq")]
+mod mlfq;
+#[cfg(not(any(feature = "sched_cfs", feature = "sched_fifo", feature = "sched_mlfq")))]
+mod stride;
+
+use alloc::sync::Arc;
+use crate::task::TaskControlBlock;
+
+/// Priority of a new task
+pub const DEFAULT_PRIORITY: usize = 16;
+/// The lowest priority a task can set, a higher one gets a larger share of the CPU
+pub const MIN_PRIORITY: usize = 2;
+/// The highest priority a task can set, so the share of every other task stays above zero
+pub const MAX_PRIORITY: usize = 1 << 10;
+
+/// A policy keeping the ready tasks and choosing the one to run next
+pub trait Scheduler {
+    fn add(&mut self, task: Arc<TaskControlBlock>);
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>>;
+    fn remove(&mut self, task: Arc<TaskControlBlock>);
+
+    /// Put back a task the timer took the CPU away from
+    fn add_preempted(&mut self, task: Arc<TaskControlBlock>) {
+        self.add(task);
+    }
+
+    /// Put back a task that was blocked or sleeping
+    fn add_woken(&mut self, task: Arc<TaskControlBlock>) {
+        self.add(task);
+    }
+
+    /// `task` has just run for `time` clock cycles
+    fn charge(&mut self, _task: &TaskControlBlock, _time: usize) {}
+}
+
+// the policy is chosen at build time by the `sched_*` features
+#[cfg(feature = "sched_cfs")]
+pub use cfs::{CfsScheduler as TaskManager, SchedEntity};
+#[cfg(feature = "sched_fifo")]
+pub use fifo::{FifoScheduler as TaskManager, SchedEntity};
+#[cfg(feature = "sched_mlfq")]
+pub use mlfq::{MlfqScheduler as TaskManager, SchedEntity};
+#[cfg(not(any(feature = "sched_cfs", feature = "sched_fifo", feature = "sched_mlfq")))]
+pub use stride::{StrideScheduler as TaskManager, SchedEntity};
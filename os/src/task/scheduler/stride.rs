@@ -0,0 +1,55 @@
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use super::Scheduler;
+use crate::task::TaskControlBlock;
+
+/// The pass a task advances by each time it runs is `BIG_STRIDE / priority`
+const BIG_STRIDE: u64 = 1 << 20;
+
+/// Scheduling state of a task
+#[derive(Default)]
+pub struct SchedEntity {
+    pass: u64,
+}
+
+/// Stride scheduling, the ready task with the smallest pass runs next,
+/// so each task gets CPU time in proportion to its priority.
+pub struct StrideScheduler {
+    /// ordered by pass, then by the order the tasks were added
+    ready: BTreeMap<(u64, usize), Arc<TaskControlBlock>>,
+    seq: usize,
+    /// pass of the task fetched last
+    current_pass: u64,
+}
+
+impl StrideScheduler {
+    pub fn new() -> Self {
+        Self { ready: BTreeMap::new(), seq: 0, current_pass: 0 }
+    }
+}
+
+impl Scheduler for StrideScheduler {
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        let mut task_inner = task.inner_exclusive_access();
+        // a new or long blocked task does not get the time it missed all at once
+        task_inner.sched.pass = task_inner.sched.pass.max(self.current_pass);
+        let key = (task_inner.sched.pass, self.seq);
+        drop(task_inner);
+        self.seq += 1;
+        self.ready.insert(key, task);
+    }
+
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        let ((pass, _), task) = self.ready.pop_first()?;
+        self.current_pass = pass;
+        let mut task_inner = task.inner_exclusive_access();
+        // at least 1, or the pass of the task would never advance
+        task_inner.sched.pass += (BIG_STRIDE / task_inner.priority as u64).max(1);
+        drop(task_inner);
+        Some(task)
+    }
+
+    fn remove(&mut self, task: Arc<TaskControlBlock>) {
+        self.ready.retain(|_, t| !Arc::ptr_eq(t, &task));
+    }
+}
@@ -1,10 +1,12 @@
 use alloc::sync::{Arc, Weak};
-use core::cell::RefMut;
+use core::sync::atomic::AtomicBool;
+use spin::MutexGuard;
 use super::TaskContext;
 use crate::mm::PhysPageNum;
 use crate::sync::UPSafeCell;
 use crate::task::id::{KernelStack, TaskUserRes};
 use crate::task::process::ProcessControlBlock;
+use crate::task::scheduler::{SchedEntity, DEFAULT_PRIORITY};
 use crate::trap::TrapContext;
 
 #[derive(Copy, Clone, PartialEq)]
@@ -19,6 +21,8 @@ pub struct TaskControlBlock {
     pub process: Weak<ProcessControlBlock>,
     pub tid: usize,
     pub kstack: KernelStack,
+    /// set while a hart runs it, until its context is saved
+    pub on_cpu: AtomicBool,
     // mutable
     inner: UPSafeCell<TaskControlBlockInner>,
 }
@@ -29,38 +33,46 @@ pub struct TaskControlBlockInner {
     pub task_cx: TaskContext,
     pub trap_cx_ppn: PhysPageNum,
     pub exit_code: Option<i32>,
+    /// share of the CPU under the stride scheduler
+    pub priority: usize,
+    pub sched: SchedEntity,
 }
 
 impl TaskControlBlock {
-    pub fn inner_exclusive_access(&self) -> RefMut<'_, TaskControlBlockInner> {
+    pub fn inner_exclusive_access(&self) -> MutexGuard<'_, TaskControlBlockInner> {
         self.inner.exclusive_access()
     }
     
+    /// Return None if out of memory
     pub fn new(
         process: Arc<ProcessControlBlock>,
         ustack_base: usize,
         alloc_user_res: bool,
-    ) -> Self {
+    ) -> Option<Self> {
+        // the kernel stack goes first, so that nothing of the process is left on failure
+        let kstack = KernelStack::new()?;
+        let kstack_top = kstack.get_top();
         let res = TaskUserRes::new(
             process.clone(),
             ustack_base,
             alloc_user_res,
-        );
+        )?;
         let trap_cx_ppn = res.trap_cx_ppn();
-        let kstack = KernelStack::new();
-        let kstack_top = kstack.get_top();
-        Self {
+        Some(Self {
             process: Arc::downgrade(&process),
             tid: res.tid,
             kstack,
+            on_cpu: AtomicBool::new(false),
             inner: unsafe { UPSafeCell::new(TaskControlBlockInner {
                 res: Some(res),
                 task_status: TaskStatus::Ready,
                 task_cx: TaskContext::goto_trap_return(kstack_top),
                 trap_cx_ppn,
                 exit_code: None,
+                priority: DEFAULT_PRIORITY,
+                sched: SchedEntity::default(),
             })},
-        }
+        })
     }
     
     pub fn get_process(&self) -> Arc<ProcessControlBlock> {
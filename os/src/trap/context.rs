@@ -9,6 +9,8 @@ pub struct TrapContext {
     pub kernel_satp: usize,
     pub kernel_sp: usize,
     pub trap_handler: usize,
+    /// the hart returning to user mode, tp is set back to it on the next trap
+    pub kernel_tp: usize,
 }
 
 impl TrapContext {
@@ -29,6 +31,7 @@ impl TrapContext {
             kernel_satp,
             kernel_sp,
             trap_handler,
+            kernel_tp: 0,
         };
         cx.set_sp(sp);
         cx
@@ -1,7 +1,9 @@
 use core::arch::{asm, global_asm};
 use riscv::register::{mtvec::TrapMode, scause::{self, Exception, Interrupt, Trap}, sie, stval, stvec, sip};
+use crate::ipi::{handle_calls, take_reschedule, take_tick};
+use crate::mm::MapPermission;
 use crate::syscall::syscall;
-use crate::task::{check_signals_error_of_current, current_add_signal, current_trap_cx, current_trap_cx_user_va, current_user_token, exit_current_and_run_next, handle_signals, suspend_current_and_run_next, SignalFlags};
+use crate::task::{check_signals_error_of_current, current_add_signal, current_handle_page_fault, current_slice_used, current_trap_cx, current_trap_cx_user_va, current_user_token, exit_current_and_run_next, handle_signals, preempt_current_and_run_next, suspend_current_and_run_next, SignalFlags};
 
 mod context;
 
@@ -20,6 +22,14 @@ pub fn init() {
     }
 }
 
+/// Drop a timer tick that came while the hart had nothing to run, so that the
+/// next task is not switched out as soon as it starts. IPIs are polled after it.
+pub fn clear_pending_tick() {
+    unsafe {
+        asm!("csrc sip, {}", in(reg) 2usize);
+    }
+}
+
 fn set_kernel_trap_entry() {
     unsafe {
         stvec::write(trap_from_kernel as usize, TrapMode::Direct);
@@ -68,10 +78,19 @@ pub fn trap_handler() -> ! {
     match scause.cause() {
         Trap::Exception(Exception::UserEnvCall) => {
             cx.sepc += 4;
-            let result = syscall(cx.x[17], [cx.x[10], cx.x[11], cx.x[12]]);
+            let result = syscall(cx.x[17], [cx.x[10], cx.x[11], cx.x[12], cx.x[13], cx.x[14], cx.x[15]]);
             cx = current_trap_cx();
             cx.x[10] = result as usize;
         }
+        Trap::Exception(Exception::StorePageFault) if current_handle_page_fault(stval, MapPermission::W) => {
+            // the page was not loaded yet or was shared by fork
+        }
+        Trap::Exception(Exception::LoadPageFault) if current_handle_page_fault(stval, MapPermission::R) => {
+            // the page was not loaded yet
+        }
+        Trap::Exception(Exception::InstructionPageFault) if current_handle_page_fault(stval, MapPermission::X) => {
+            // the page was not loaded yet
+        }
         Trap::Exception(Exception::StoreFault) |
         Trap::Exception(Exception::StorePageFault) |
         Trap::Exception(Exception::LoadFault) |
@@ -99,8 +118,19 @@ pub fn trap_handler() -> ! {
             unsafe {
                 asm! {"csrw sip, {sip}", sip = in(reg) sip ^ 2};
             } // clear the Supervisor Software Interrupt bit
-            check_timer();
-            suspend_current_and_run_next();
+            handle_calls();
+            // both are taken, a tick may come with a reschedule request
+            let tick = take_tick();
+            let reschedule = take_reschedule();
+            if tick {
+                check_timer();
+            }
+            if tick && current_slice_used() {
+                preempt_current_and_run_next();
+            } else if reschedule {
+                // a task has been woken up, the current one keeps its place in the queues
+                suspend_current_and_run_next();
+            }
         }
         _ => {
             panic!("Unsupported trap {:?}, stval = {:#x}", scause.cause(), stval);
@@ -1,5 +1,3 @@
-//! It only works on a single CPU!
-
 #![no_std]
 #![no_main]
 
@@ -9,7 +7,7 @@ extern crate alloc;
 
 use alloc::vec::Vec;
 use core::ptr::addr_of_mut;
-use core::sync::atomic::{Ordering, compiler_fence};
+use core::sync::atomic::{Ordering, fence};
 use user_lib::{exit, get_time, thread_create, waittid};
 
 static mut A: usize = 0;
@@ -35,9 +33,9 @@ fn lock(id: usize) {
         FLAG[id] = true;
         let j = 1 - id;
         TURN = j;
-        // Tell the compiler not to reorder memory operations
-        // across this fence.
-        compiler_fence(Ordering::SeqCst);
+        // Tell the compiler and the other harts not to reorder
+        // memory operations across this fence.
+        fence(Ordering::SeqCst);
         // Why do we need to use volatile_read here?
         // Otherwise the compiler will assume that they will never
         // be changed on this thread. Thus, they will be accessed
@@ -48,6 +46,8 @@ fn lock(id: usize) {
 
 fn unlock(id: usize) {
     unsafe {
+        // the critical section is seen before the lock is released
+        fence(Ordering::SeqCst);
         FLAG[id] = false;
     }
 }
@@ -0,0 +1,51 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user_lib::{brk, fork, sbrk, wait};
+
+const PAGE_SIZE: usize = 4096;
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    // move the break by hand and touch the new pages
+    let old_brk = sbrk(0);
+    assert!(old_brk > 0);
+    assert_eq!(sbrk(2 * PAGE_SIZE as isize), old_brk);
+    let ptr = old_brk as usize as *mut u8;
+    for i in 0..2 * PAGE_SIZE {
+        unsafe { ptr.add(i).write_volatile(i as u8) };
+    }
+    assert_eq!(sbrk(-(2 * PAGE_SIZE as isize)), old_brk + 2 * PAGE_SIZE as isize);
+    assert_eq!(sbrk(0), old_brk);
+    // the break can not go below the heap or into other areas
+    assert_eq!(brk(1), old_brk);
+    assert_eq!(brk(usize::MAX), old_brk);
+    println!("brk test passed!");
+
+    // far larger than the initial heap
+    let len = 1 << 18;
+    let mut v: Vec<usize> = Vec::with_capacity(len);
+    for i in 0..len {
+        v.push(i * 3);
+    }
+    let pid = fork();
+    if pid == 0 {
+        for x in v.iter_mut() {
+            *x = 0;
+        }
+        return 0;
+    }
+    let mut exit_code = 0;
+    assert_eq!(wait(&mut exit_code), pid);
+    for (i, x) in v.iter().enumerate() {
+        assert_eq!(*x, i * 3);
+    }
+    drop(v);
+    println!("brktest passed!");
+    0
+}
@@ -0,0 +1,56 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{fork, mmap_huge, munmap, wait, MmapProt};
+
+const PAGE_SIZE: usize = 4096;
+const HUGE_PAGE_SIZE: usize = 2 << 20;
+const LEN: usize = 8 << 20;
+
+fn page(start: usize, page: usize) -> *mut usize {
+    (start + page * PAGE_SIZE) as *mut usize
+}
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    // an unaligned start is refused
+    assert_eq!(mmap_huge(HUGE_PAGE_SIZE + PAGE_SIZE, LEN, MmapProt::READ | MmapProt::WRITE), -1);
+    let start = mmap_huge(0, LEN, MmapProt::READ | MmapProt::WRITE);
+    assert!(start > 0);
+    let start = start as usize;
+    assert_eq!(start % HUGE_PAGE_SIZE, 0);
+    for i in 0..LEN / PAGE_SIZE {
+        unsafe { page(start, i).write_volatile(i) };
+    }
+    for i in 0..LEN / PAGE_SIZE {
+        assert_eq!(unsafe { page(start, i).read_volatile() }, i);
+    }
+
+    // the child gets a copy of its own
+    let pid = fork();
+    if pid == 0 {
+        for i in 0..LEN / PAGE_SIZE {
+            assert_eq!(unsafe { page(start, i).read_volatile() }, i);
+            unsafe { page(start, i).write_volatile(i + 1) };
+        }
+        return 0;
+    }
+    let mut exit_code = 0;
+    assert_eq!(wait(&mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    for i in 0..LEN / PAGE_SIZE {
+        assert_eq!(unsafe { page(start, i).read_volatile() }, i);
+    }
+
+    // huge pages are not split
+    assert_eq!(munmap(start + PAGE_SIZE, PAGE_SIZE), -1);
+    assert_eq!(munmap(start + HUGE_PAGE_SIZE, HUGE_PAGE_SIZE), 0);
+    assert_eq!(munmap(start, LEN), -1);
+    assert_eq!(munmap(start, HUGE_PAGE_SIZE), 0);
+    assert_eq!(munmap(start + 2 * HUGE_PAGE_SIZE, LEN - 2 * HUGE_PAGE_SIZE), 0);
+    println!("hugetest passed!");
+    0
+}
@@ -0,0 +1,95 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec;
+use alloc::vec::Vec;
+use user_lib::{
+    close, mmap_file, mprotect, msync, munmap, open, read, write, MmapFlags, MmapProt, OpenFlags,
+};
+
+const PAGE_SIZE: usize = 4096;
+const START: usize = 0x1000_0000;
+const FILE_SIZE: usize = 2 * PAGE_SIZE + 100;
+const FILE_NAME: &str = "mmap_file\0";
+
+fn pattern(i: usize) -> u8 {
+    (i % 251) as u8
+}
+
+fn read_file() -> Vec<u8> {
+    let fd = open(FILE_NAME, OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let mut buffer = vec![0u8; FILE_SIZE + PAGE_SIZE];
+    let len = read(fd as usize, &mut buffer) as usize;
+    close(fd as usize);
+    buffer.truncate(len);
+    buffer
+}
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    let data: Vec<u8> = (0..FILE_SIZE).map(pattern).collect();
+    let fd = open(FILE_NAME, OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    write(fd as usize, &data);
+    close(fd as usize);
+
+    let rw = MmapProt::READ | MmapProt::WRITE;
+    let fd = open(FILE_NAME, OpenFlags::RDWR);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    // misaligned offsets and bad descriptors are refused
+    assert_eq!(mmap_file(START, PAGE_SIZE, rw, MmapFlags::SHARED, fd, 1), -1);
+    assert_eq!(mmap_file(START, PAGE_SIZE, rw, MmapFlags::SHARED, 100, 0), -1);
+    assert_eq!(mmap_file(START, PAGE_SIZE, rw, MmapFlags::SHARED | MmapFlags::PRIVATE, fd, 0), -1);
+
+    // pages are read from the file, the rest of the last page is zero
+    assert_eq!(mmap_file(START, 3 * PAGE_SIZE, rw, MmapFlags::SHARED, fd, 0), START as isize);
+    for i in 0..3 * PAGE_SIZE {
+        let v = unsafe { ((START + i) as *const u8).read_volatile() };
+        assert_eq!(v, if i < FILE_SIZE { pattern(i) } else { 0 });
+    }
+    // shared writes reach the file on msync, without extending it
+    unsafe {
+        ((START + PAGE_SIZE) as *mut u8).write_volatile(0xaa);
+        ((START + FILE_SIZE) as *mut u8).write_volatile(0xbb);
+    }
+    assert_eq!(msync(START, 3 * PAGE_SIZE), 0);
+    let content = read_file();
+    assert_eq!(content.len(), FILE_SIZE);
+    assert_eq!(content[PAGE_SIZE], 0xaa);
+    // and on munmap
+    unsafe { (START as *mut u8).write_volatile(0xcc) };
+    assert_eq!(munmap(START, 3 * PAGE_SIZE), 0);
+    assert_eq!(read_file()[0], 0xcc);
+    println!("shared file mapping test passed!");
+
+    // private writes never reach the file
+    let offset = PAGE_SIZE;
+    assert_eq!(mmap_file(START, PAGE_SIZE, rw, MmapFlags::PRIVATE, fd, offset), START as isize);
+    assert_eq!(unsafe { (START as *const u8).read_volatile() }, 0xaa);
+    unsafe { (START as *mut u8).write_volatile(0xdd) };
+    assert_eq!(unsafe { (START as *const u8).read_volatile() }, 0xdd);
+    assert_eq!(munmap(START, PAGE_SIZE), 0);
+    assert_eq!(read_file()[PAGE_SIZE], 0xaa);
+    close(fd);
+    println!("private file mapping test passed!");
+
+    // a read-only file can not be mapped shared and writable
+    let fd = open(FILE_NAME, OpenFlags::RDONLY) as usize;
+    assert_eq!(mmap_file(START, PAGE_SIZE, rw, MmapFlags::SHARED, fd, 0), -1);
+    assert_eq!(mmap_file(START, PAGE_SIZE, rw, MmapFlags::PRIVATE, fd, 0), START as isize);
+    // nor made writable after it is mapped read-only
+    let shared = START + PAGE_SIZE;
+    assert_eq!(mmap_file(shared, PAGE_SIZE, MmapProt::READ, MmapFlags::SHARED, fd, 0), shared as isize);
+    assert_eq!(mprotect(shared, PAGE_SIZE, rw), -1);
+    assert_eq!(mprotect(START, 2 * PAGE_SIZE, rw), -1);
+    assert_eq!(mprotect(shared, PAGE_SIZE, MmapProt::READ), 0);
+    close(fd);
+    println!("mmap_filetest passed!");
+    0
+}
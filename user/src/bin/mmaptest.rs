@@ -0,0 +1,70 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{fork, mmap, mprotect, munmap, wait, MmapProt};
+
+const PAGE_SIZE: usize = 4096;
+const START: usize = 0x1000_0000;
+
+fn fill(start: usize, len: usize, seed: u8) {
+    for i in 0..len {
+        unsafe { ((start + i) as *mut u8).write_volatile(seed.wrapping_add(i as u8)) };
+    }
+}
+
+fn check(start: usize, len: usize, seed: u8) {
+    for i in 0..len {
+        let v = unsafe { ((start + i) as *const u8).read_volatile() };
+        assert_eq!(v, seed.wrapping_add(i as u8));
+    }
+}
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    let rw = MmapProt::READ | MmapProt::WRITE;
+    assert_eq!(mmap(START, 3 * PAGE_SIZE, rw), START as isize);
+    fill(START, 3 * PAGE_SIZE, 1);
+    check(START, 3 * PAGE_SIZE, 1);
+    // overlapping and malformed requests are refused
+    assert_eq!(mmap(START + PAGE_SIZE, PAGE_SIZE, rw), -1);
+    assert_eq!(mmap(START + 1, PAGE_SIZE, rw), -1);
+    assert_eq!(mmap(START + 4 * PAGE_SIZE, PAGE_SIZE, MmapProt::empty()), -1);
+    println!("mmap test passed!");
+
+    // punch a hole in the middle of the area
+    assert_eq!(munmap(START + PAGE_SIZE, PAGE_SIZE), 0);
+    assert_eq!(munmap(START + PAGE_SIZE, PAGE_SIZE), -1);
+    check(START, PAGE_SIZE, 1);
+    check(START + 2 * PAGE_SIZE, PAGE_SIZE, 1u8.wrapping_add((2 * PAGE_SIZE) as u8));
+    assert_eq!(mmap(START + PAGE_SIZE, PAGE_SIZE, rw), (START + PAGE_SIZE) as isize);
+    println!("munmap test passed!");
+
+    assert_eq!(mprotect(START, PAGE_SIZE, MmapProt::READ), 0);
+    check(START, PAGE_SIZE, 1);
+    assert_eq!(mprotect(START + 8 * PAGE_SIZE, PAGE_SIZE, MmapProt::READ), -1);
+    println!("mprotect test passed!");
+
+    // let the kernel choose where to map
+    let addr = mmap(0, 2 * PAGE_SIZE, rw);
+    assert!(addr > 0);
+    let addr = addr as usize;
+    fill(addr, 2 * PAGE_SIZE, 7);
+    let pid = fork();
+    if pid == 0 {
+        // the child gets its own copy on write
+        check(addr, 2 * PAGE_SIZE, 7);
+        fill(addr, 2 * PAGE_SIZE, 9);
+        check(addr, 2 * PAGE_SIZE, 9);
+        return 0;
+    }
+    let mut exit_code: i32 = 0;
+    assert_eq!(wait(&mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    check(addr, 2 * PAGE_SIZE, 7);
+    assert_eq!(munmap(addr, 2 * PAGE_SIZE), 0);
+    println!("mmaptest passed!");
+    0
+}
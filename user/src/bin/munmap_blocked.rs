@@ -0,0 +1,49 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, mmap, munmap, pipe, read, sleep, thread_create, waittid, write, MmapProt};
+
+const PAGE_SIZE: usize = 4096;
+const START: usize = 0x1000_0000;
+/// likely to take the frame freed by unmapping START
+const OTHER: usize = 0x2000_0000;
+/// no more than a pipe holds
+const LEN: usize = 32;
+/// time for the reader to block on the empty pipe
+const BLOCK_MS: usize = 50;
+
+static mut PIPE_FD: [usize; 2] = [0; 2];
+
+fn reader() -> ! {
+    let buffer = unsafe { core::slice::from_raw_parts_mut(START as *mut u8, LEN) };
+    // the buffer is unmapped while this blocks, so it must not be touched afterwards
+    exit(read(unsafe { PIPE_FD[0] }, buffer) as i32)
+}
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    let rw = MmapProt::READ | MmapProt::WRITE;
+    assert_eq!(pipe(unsafe { &mut *(&raw mut PIPE_FD) }), 0);
+    assert_eq!(mmap(START, PAGE_SIZE, rw), START as isize);
+    unsafe { (START as *mut u8).write_volatile(1) };
+    let tid = thread_create(reader as usize, 0);
+    sleep(BLOCK_MS);
+    // the reader still holds the frame, so it is not handed out again
+    assert_eq!(munmap(START, PAGE_SIZE), 0);
+    assert_eq!(mmap(OTHER, PAGE_SIZE, rw), OTHER as isize);
+    for i in 0..PAGE_SIZE {
+        unsafe { ((OTHER + i) as *mut u8).write_volatile(0x5a) };
+    }
+    assert_eq!(write(unsafe { PIPE_FD[1] }, &[0xa5; LEN]), LEN as isize);
+    assert_eq!(waittid(tid as usize), LEN as isize);
+    for i in 0..PAGE_SIZE {
+        let byte = unsafe { ((OTHER + i) as *const u8).read_volatile() };
+        assert_eq!(byte, 0x5a, "a blocked read wrote into an unmapped frame");
+    }
+    assert_eq!(munmap(OTHER, PAGE_SIZE), 0);
+    println!("munmap_blocked passed!");
+    0
+}
@@ -0,0 +1,41 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{fork, mmap, munmap, wait, MmapProt};
+
+const PAGE_SIZE: usize = 4096;
+/// more than the physical memory and the swap area together
+const LEN: usize = 256 << 20;
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    let pid = fork();
+    if pid == 0 {
+        let start = mmap(0, LEN, MmapProt::READ | MmapProt::WRITE);
+        assert!(start > 0);
+        for page in 0..LEN / PAGE_SIZE {
+            let ptr = (start as usize + page * PAGE_SIZE) as *mut usize;
+            unsafe { ptr.write_volatile(page) };
+        }
+        panic!("the memory hog should have been killed");
+    }
+    // the child holds the most memory and is killed instead of the kernel panicking
+    let mut exit_code = 0;
+    assert_eq!(wait(&mut exit_code), pid);
+    assert_eq!(exit_code, -9);
+
+    // and its memory is usable again
+    let len = 16 << 20;
+    let start = mmap(0, len, MmapProt::READ | MmapProt::WRITE);
+    assert!(start > 0);
+    for page in 0..len / PAGE_SIZE {
+        let ptr = (start as usize + page * PAGE_SIZE) as *mut usize;
+        unsafe { ptr.write_volatile(page) };
+    }
+    assert_eq!(munmap(start as usize, len), 0);
+    println!("oomtest passed!");
+    0
+}
@@ -0,0 +1,57 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, fork, get_time, set_priority, waitpid, yield_};
+
+/// enough children for none of them to get a hart of its own with up to 4 harts
+const PRIORITIES: [isize; 16] = [4, 8, 12, 16, 4, 8, 12, 16, 4, 8, 12, 16, 4, 8, 12, 16];
+/// time for all the children to be forked before they start counting
+const START_DELAY_MS: isize = 100;
+const RUN_MS: isize = 2000;
+/// how far the count per priority of a child may be off the average, in percent
+const TOLERANCE: usize = 25;
+
+/// Count how many times the clock can be read until `end`
+fn spin(start: isize, end: isize) -> usize {
+    while get_time() < start {
+        yield_();
+    }
+    let mut count = 0;
+    while get_time() < end {
+        count += 1;
+    }
+    count
+}
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    assert_eq!(set_priority(1), -1);
+    assert_eq!(set_priority(0), -1);
+    assert_eq!(set_priority(1 << 20), -1);
+    let start = get_time() + START_DELAY_MS;
+    let mut pids = [0; PRIORITIES.len()];
+    for (pid, prio) in pids.iter_mut().zip(PRIORITIES) {
+        *pid = fork();
+        if *pid == 0 {
+            assert_eq!(set_priority(prio), prio);
+            exit(spin(start, start + RUN_MS) as i32);
+        }
+        assert!(*pid > 0);
+    }
+    let mut shares = [0; PRIORITIES.len()];
+    for (share, (pid, prio)) in shares.iter_mut().zip(pids.iter().zip(PRIORITIES)) {
+        let mut count = 0;
+        assert_eq!(waitpid(*pid as usize, &mut count), *pid);
+        *share = count as usize / prio as usize;
+        println!("priority {}: counted {}, {} per priority", prio, count, share);
+    }
+    let average = shares.iter().sum::<usize>() / shares.len();
+    for share in shares {
+        assert!(share.abs_diff(average) * 100 <= average * TOLERANCE);
+    }
+    println!("priority test passed!");
+    0
+}
@@ -0,0 +1,75 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, fork, get_time, set_priority, sleep, waitpid, yield_};
+
+// Run with SCHED=cfs, the ready task that has run the least for its priority runs
+// next, and one waking up is put at most a time slice ahead of the others.
+
+/// enough children for none of them to get a hart of its own with up to 4 harts
+const PRIORITIES: [isize; 16] = [4, 8, 12, 16, 4, 8, 12, 16, 4, 8, 12, 16, 4, 8, 12, 16];
+/// the priority every child starts with
+const DEFAULT_PRIORITY: isize = 16;
+/// time for all the children to be forked before they start counting
+const START_DELAY_MS: isize = 100;
+const RUN_MS: isize = 2000;
+/// how long the sleeping child waits before it counts for the rest of the run
+const SLEEP_MS: isize = RUN_MS / 2;
+/// how far the count per priority of a child may be off the average, in percent
+const TOLERANCE: usize = 25;
+
+/// Count how many times the clock can be read from `start` until `end`
+fn spin(start: isize, end: isize) -> usize {
+    while get_time() < start {
+        yield_();
+    }
+    let mut count = 0;
+    while get_time() < end {
+        count += 1;
+    }
+    count
+}
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    let start = get_time() + START_DELAY_MS;
+    let end = start + RUN_MS;
+    let mut pids = [0; PRIORITIES.len()];
+    for (pid, prio) in pids.iter_mut().zip(PRIORITIES) {
+        *pid = fork();
+        if *pid == 0 {
+            assert_eq!(set_priority(prio), prio);
+            exit(spin(start, end) as i32);
+        }
+        assert!(*pid > 0);
+    }
+    // a task that ran nothing while it slept must not then keep a hart to itself to catch up
+    let sleeper = fork();
+    if sleeper == 0 {
+        sleep((start + SLEEP_MS - get_time()) as usize);
+        exit(spin(start + SLEEP_MS, end) as i32);
+    }
+    assert!(sleeper > 0);
+    let mut shares = [0; PRIORITIES.len()];
+    for (share, (pid, prio)) in shares.iter_mut().zip(pids.iter().zip(PRIORITIES)) {
+        let mut count = 0;
+        assert_eq!(waitpid(*pid as usize, &mut count), *pid);
+        *share = count as usize / prio as usize;
+        println!("priority {}: counted {}, {} per priority", prio, count, share);
+    }
+    let average = shares.iter().sum::<usize>() / shares.len();
+    for share in shares {
+        assert!(share.abs_diff(average) * 100 <= average * TOLERANCE);
+    }
+    let mut count = 0;
+    assert_eq!(waitpid(sleeper as usize, &mut count), sleeper);
+    // it counted for half of the run, as much as the others did in that time
+    let share = count as usize * (RUN_MS / (RUN_MS - SLEEP_MS)) as usize / DEFAULT_PRIORITY as usize;
+    println!("sleeper: counted {}, {} per priority", count, share);
+    assert!(share.abs_diff(average) * 100 <= average * TOLERANCE);
+    println!("sched_cfs test passed!");
+    0
+}
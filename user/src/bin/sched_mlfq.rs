@@ -0,0 +1,90 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, fork, get_time, sleep, waitpid, yield_};
+
+// Run with SCHED=mlfq, the CPU-bound children sink to the lowest level while
+// the one that keeps sleeping rises back to the top each time it wakes up.
+
+/// enough children for each hart to have several of them waiting with up to 4 harts
+const HOGS: usize = 24;
+/// time for all the children to be forked before they start counting
+const START_DELAY_MS: isize = 100;
+/// time for the CPU-bound children to use up a few time slices and drop down
+const SETTLE_MS: isize = 200;
+const RUN_MS: isize = 2000;
+const SLEEP_MS: usize = 20;
+/// one time slice
+const SLICE_MS: isize = 10;
+/// how late the sleeping child may be on average, a waiting task that is not
+/// put ahead of the CPU-bound ones is several time slices late
+const MAX_LATENCY_MS: isize = 2 * SLICE_MS;
+/// how far the count of a CPU-bound child may be off the average, in percent
+const TOLERANCE: usize = 25;
+
+/// Count how many times the clock can be read until `end`
+fn spin(start: isize, end: isize) -> usize {
+    while get_time() < start {
+        yield_();
+    }
+    let mut count = 0;
+    while get_time() < end {
+        count += 1;
+    }
+    count
+}
+
+/// Sleep again and again until `end`, return how late it woke up on average
+fn interact(start: isize, end: isize) -> isize {
+    while get_time() < start {
+        yield_();
+    }
+    let mut late = 0;
+    let mut rounds = 0;
+    while get_time() < end {
+        let before = get_time();
+        sleep(SLEEP_MS);
+        late += get_time() - before - SLEEP_MS as isize;
+        rounds += 1;
+    }
+    late / rounds
+}
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    let start = get_time() + START_DELAY_MS;
+    let end = start + RUN_MS;
+    let mut pids = [0; HOGS];
+    for pid in pids.iter_mut() {
+        *pid = fork();
+        if *pid == 0 {
+            exit(spin(start, end) as i32);
+        }
+        assert!(*pid > 0);
+    }
+    let interactive = fork();
+    if interactive == 0 {
+        exit(interact(start + SETTLE_MS, end) as i32);
+    }
+    assert!(interactive > 0);
+    let mut latency = 0;
+    assert_eq!(waitpid(interactive as usize, &mut latency), interactive);
+    println!("woke up {} ms late on average", latency);
+    assert!((0..=MAX_LATENCY_MS as i32).contains(&latency));
+    let mut counts = [0; HOGS];
+    for (count, pid) in counts.iter_mut().zip(pids) {
+        let mut exit_code = 0;
+        assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+        *count = exit_code as usize;
+    }
+    // none of the lowest level starves
+    let average = counts.iter().sum::<usize>() / counts.len();
+    for count in counts {
+        assert!(count.abs_diff(average) * 100 <= average * TOLERANCE);
+    }
+    println!("sched_mlfq test passed!");
+    0
+}
@@ -0,0 +1,97 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{fork, mprotect, shmat, shmctl, shmdt, shmget, wait, IPC_PRIVATE, IPC_RMID, MmapProt, SHM_RDONLY};
+
+const PAGE_SIZE: usize = 4096;
+const KEY: usize = 0x5348;
+/// a segment never removed
+const KEPT_KEY: usize = 0x5349;
+const SIZE: usize = 2 * PAGE_SIZE;
+const START: usize = 0x1000_0000;
+
+fn read(addr: usize) -> usize {
+    unsafe { (addr as *const usize).read_volatile() }
+}
+
+fn write(addr: usize, value: usize) {
+    unsafe { (addr as *mut usize).write_volatile(value) };
+}
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    let shmid = shmget(KEY, SIZE);
+    assert!(shmid >= 0);
+    let shmid = shmid as usize;
+    // the same key finds the same segment, unless it is too small
+    assert_eq!(shmget(KEY, PAGE_SIZE), shmid as isize);
+    assert_eq!(shmget(KEY, 2 * SIZE), -1);
+    let private_id = shmget(IPC_PRIVATE, PAGE_SIZE);
+    assert!(private_id >= 0 && private_id != shmid as isize);
+    assert_eq!(shmctl(private_id as usize, IPC_RMID), 0);
+    assert_eq!(shmat(shmid, START + 1, 0), -1);
+
+    assert_eq!(shmat(shmid, START, 0), START as isize);
+    write(START, 1);
+    write(START + PAGE_SIZE, 2);
+
+    // a child writes through the inherited attachment and a new one
+    let pid = fork();
+    if pid == 0 {
+        assert_eq!(read(START), 1);
+        write(START, 10);
+        let shmid = shmget(KEY, 0) as usize;
+        let start = shmat(shmid, 0, 0);
+        assert!(start > 0);
+        let start = start as usize;
+        assert_eq!(read(start + PAGE_SIZE), 2);
+        write(start + PAGE_SIZE, 20);
+        assert_eq!(shmdt(start), 0);
+        return 0;
+    }
+    let mut exit_code = 0;
+    assert_eq!(wait(&mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    assert_eq!(read(START), 10);
+    assert_eq!(read(START + PAGE_SIZE), 20);
+    println!("shared memory fork test passed!");
+
+    // a removed segment stays attached until it is detached
+    assert_eq!(shmctl(shmid, IPC_RMID), 0);
+    assert_eq!(shmctl(shmid, IPC_RMID), -1);
+    assert_eq!(shmat(shmid, 0, 0), -1);
+    assert_eq!(read(START), 10);
+    assert_eq!(shmdt(START), 0);
+    assert_eq!(shmdt(START), -1);
+
+    // a new segment with the same key starts cleared
+    let shmid = shmget(KEY, SIZE) as usize;
+    let start = shmat(shmid, 0, SHM_RDONLY);
+    assert!(start > 0);
+    assert_eq!(read(start as usize), 0);
+    // and mprotect can not make a read-only attachment writable
+    assert_eq!(mprotect(start as usize, PAGE_SIZE, MmapProt::READ | MmapProt::WRITE), -1);
+    assert_eq!(shmctl(shmid, IPC_RMID), 0);
+    assert_eq!(shmdt(start as usize), 0);
+
+    // a segment that is not removed goes away with its last attachment
+    let shmid = shmget(KEPT_KEY, SIZE);
+    assert!(shmid >= 0);
+    let shmid = shmid as usize;
+    let start = shmat(shmid, 0, 0);
+    assert!(start > 0);
+    write(start as usize, 3);
+    assert_eq!(shmdt(start as usize), 0);
+    assert_eq!(shmat(shmid, 0, 0), -1);
+    assert_eq!(shmctl(shmid, IPC_RMID), -1);
+    let shmid = shmget(KEPT_KEY, SIZE) as usize;
+    let start = shmat(shmid, 0, 0);
+    assert!(start > 0);
+    assert_eq!(read(start as usize), 0);
+    assert_eq!(shmdt(start as usize), 0);
+    println!("shmtest passed!");
+    0
+}
@@ -0,0 +1,48 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, thread_create, waittid};
+
+/// each level takes a page of stack, far beyond the initial 8 KiB
+const DEPTH: usize = 128;
+
+#[inline(never)]
+fn recurse(depth: usize) -> usize {
+    let mut frame = [0u8; 4096];
+    for (i, byte) in frame.iter_mut().enumerate() {
+        *byte = (i + depth) as u8;
+    }
+    let below = if depth == 0 { 0 } else { recurse(depth - 1) };
+    // read the frame back after the deeper levels have grown the stack
+    below + core::hint::black_box(&frame).iter().map(|byte| *byte as usize).sum::<usize>()
+}
+
+fn expected() -> usize {
+    (0..=DEPTH)
+        .map(|depth| (0..4096).map(|i| ((i + depth) as u8) as usize).sum::<usize>())
+        .sum()
+}
+
+fn thread_main() -> ! {
+    exit(if recurse(DEPTH) == expected() { 0 } else { 1 })
+}
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    assert_eq!(recurse(DEPTH), expected());
+    println!("main thread stack grew!");
+    // stacks of threads grow too, without running into each other
+    let tids = [
+        thread_create(thread_main as usize, 0),
+        thread_create(thread_main as usize, 0),
+    ];
+    for tid in tids {
+        assert!(tid > 0);
+        assert_eq!(waittid(tid as usize), 0);
+    }
+    println!("stack_growth passed!");
+    0
+}
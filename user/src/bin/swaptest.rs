@@ -0,0 +1,87 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, fork, mmap, munmap, pipe, read, waitpid, write, MmapProt};
+
+const PAGE_SIZE: usize = 4096;
+/// more than the 128 MiB of physical memory
+const LEN: usize = 144 << 20;
+/// most of the physical memory, held by a process while another one forks
+const HELD_LEN: usize = 120 << 20;
+/// each needs frames for page tables and a kernel stack
+const FORKS: usize = 64;
+
+fn value(page: usize, round: usize) -> usize {
+    page * 7 + round
+}
+
+fn fill(start: usize, len: usize, round: usize) {
+    for page in 0..len / PAGE_SIZE {
+        let ptr = (start + page * PAGE_SIZE) as *mut usize;
+        unsafe {
+            ptr.write_volatile(value(page, round));
+            ptr.add(PAGE_SIZE / size_of::<usize>() - 1).write_volatile(!value(page, round));
+        }
+    }
+}
+
+fn check(start: usize, len: usize, round: usize) {
+    for page in 0..len / PAGE_SIZE {
+        let ptr = (start + page * PAGE_SIZE) as *const usize;
+        unsafe {
+            assert_eq!(ptr.read_volatile(), value(page, round));
+            assert_eq!(ptr.add(PAGE_SIZE / size_of::<usize>() - 1).read_volatile(), !value(page, round));
+        }
+    }
+}
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    let start = mmap(0, LEN, MmapProt::READ | MmapProt::WRITE);
+    assert!(start > 0);
+    let start = start as usize;
+    fill(start, LEN, 0);
+    check(start, LEN, 0);
+    println!("swap test passed!");
+
+    // pages swapped in and written again go back to the swap area
+    fill(start, LEN, 1);
+    check(start, LEN, 1);
+    assert_eq!(munmap(start, LEN), 0);
+    println!("swap in test passed!");
+
+    // a process blocked on a pipe gives up its pages for the allocations of another
+    let mut ready = [0usize; 2];
+    let mut done = [0usize; 2];
+    assert_eq!(pipe(&mut ready), 0);
+    assert_eq!(pipe(&mut done), 0);
+    let mut byte = [0u8; 1];
+    let holder = fork();
+    if holder == 0 {
+        let start = mmap(0, HELD_LEN, MmapProt::READ | MmapProt::WRITE);
+        assert!(start > 0);
+        fill(start as usize, HELD_LEN, 2);
+        write(ready[1], &byte);
+        read(done[0], &mut byte);
+        check(start as usize, HELD_LEN, 2);
+        exit(0);
+    }
+    read(ready[0], &mut byte);
+    let mut exit_code = 0;
+    for _ in 0..FORKS {
+        let pid = fork();
+        assert!(pid >= 0, "fork failed while another process held the memory");
+        if pid == 0 {
+            exit(0);
+        }
+        waitpid(pid as usize, &mut exit_code);
+    }
+    write(done[1], &byte);
+    waitpid(holder as usize, &mut exit_code);
+    assert_eq!(exit_code, 0);
+    println!("swaptest passed!");
+    0
+}
@@ -0,0 +1,94 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, exit, fork, get_time, pipe, read, waitpid, write};
+
+// Time the switches between address spaces that forktest and pipetest make,
+// most of it is refilling the TLB when it is flushed on every switch.
+// A benchmark run by hand, compare the times printed by kernels built with and
+// without ASIDs on the same machine, since they vary too much to check against a bound.
+
+const FORKS: usize = 200;
+const ROUND_TRIPS: usize = 2000;
+const PAGE_SIZE: usize = 4096;
+/// pages each side touches between two switches
+const WORKING_SET: usize = 64;
+
+static mut PAGES: [u8; WORKING_SET * PAGE_SIZE] = [0; WORKING_SET * PAGE_SIZE];
+
+fn touch_pages() {
+    for page in 0..WORKING_SET {
+        unsafe {
+            let byte = (&raw mut PAGES).cast::<u8>().add(page * PAGE_SIZE);
+            byte.write_volatile(byte.read_volatile().wrapping_add(1));
+        }
+    }
+}
+
+fn report(name: &str, rounds: usize, ms: isize) {
+    println!("{}: {} rounds in {} ms, {} us each", name, rounds, ms, ms as usize * 1000 / rounds);
+}
+
+/// Fork a child that exits right away and wait for it
+fn bench_fork() -> isize {
+    let start = get_time();
+    for _ in 0..FORKS {
+        let pid = fork();
+        if pid == 0 {
+            exit(0);
+        }
+        assert!(pid > 0);
+        let mut exit_code = 0;
+        assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+        assert_eq!(exit_code, 0);
+    }
+    get_time() - start
+}
+
+/// Pass a byte back and forth between two processes through a pair of pipes
+fn bench_pipe() -> isize {
+    let mut ping = [0usize; 2];
+    let mut pong = [0usize; 2];
+    assert_eq!(pipe(&mut ping), 0);
+    assert_eq!(pipe(&mut pong), 0);
+    touch_pages();
+    let mut byte = [0u8; 1];
+    let pid = fork();
+    if pid == 0 {
+        close(ping[1]);
+        close(pong[0]);
+        for _ in 0..ROUND_TRIPS {
+            assert_eq!(read(ping[0], &mut byte), 1);
+            touch_pages();
+            assert_eq!(write(pong[1], &byte), 1);
+        }
+        exit(0);
+    }
+    assert!(pid > 0);
+    close(ping[0]);
+    close(pong[1]);
+    let start = get_time();
+    for _ in 0..ROUND_TRIPS {
+        assert_eq!(write(ping[1], &byte), 1);
+        assert_eq!(read(pong[0], &mut byte), 1);
+        touch_pages();
+    }
+    let ms = get_time() - start;
+    close(ping[1]);
+    close(pong[0]);
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    ms
+}
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    report("fork", FORKS, bench_fork());
+    report("pipe round trip", ROUND_TRIPS, bench_pipe());
+    println!("switch_bench passed!");
+    0
+}
@@ -0,0 +1,71 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use user_lib::{exit, mmap, munmap, thread_create, waittid, yield_, MmapProt};
+
+const PAGE_SIZE: usize = 4096;
+const START: usize = 0x1000_0000;
+/// takes the frames freed by unmapping START, so a stale entry reads something else
+const OTHER: usize = 0x2000_0000;
+/// one fewer than the harts, so each thread may keep one to itself
+const THREADS: usize = 3;
+const ROUNDS: usize = 100;
+
+/// the round whose page is ready to read
+static ROUND: AtomicUsize = AtomicUsize::new(0);
+/// reads done in all rounds
+static SEEN: AtomicUsize = AtomicUsize::new(0);
+static FAILED: AtomicBool = AtomicBool::new(false);
+
+fn read_start() -> usize {
+    unsafe { (START as *const usize).read_volatile() }
+}
+
+fn reader() -> ! {
+    for round in 1..=ROUNDS {
+        // yielding keeps the process's entries cached, while not depending on
+        // how fast the harts are compared with each other
+        while ROUND.load(Ordering::Acquire) != round {
+            yield_();
+        }
+        // the page of the last round is cached if this hart was not told it is gone
+        if read_start() != round {
+            FAILED.store(true, Ordering::Relaxed);
+        }
+        SEEN.fetch_add(1, Ordering::AcqRel);
+    }
+    exit(0)
+}
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    let rw = MmapProt::READ | MmapProt::WRITE;
+    assert_eq!(mmap(START, PAGE_SIZE, rw), START as isize);
+    let tids: Vec<_> = (0..THREADS)
+        .map(|_| thread_create(reader as usize, 0))
+        .collect();
+    for round in 1..=ROUNDS {
+        assert_eq!(munmap(START, PAGE_SIZE), 0);
+        assert_eq!(mmap(OTHER, PAGE_SIZE, rw), OTHER as isize);
+        unsafe { (OTHER as *mut usize).write_volatile(usize::MAX) };
+        assert_eq!(mmap(START, PAGE_SIZE, rw), START as isize);
+        unsafe { (START as *mut usize).write_volatile(round) };
+        ROUND.store(round, Ordering::Release);
+        while SEEN.load(Ordering::Acquire) != round * THREADS {
+            yield_();
+        }
+        assert_eq!(munmap(OTHER, PAGE_SIZE), 0);
+    }
+    for tid in tids {
+        assert_eq!(waittid(tid as usize), 0);
+    }
+    assert!(!FAILED.load(Ordering::Relaxed), "a thread read an unmapped page");
+    println!("tlb_shootdown passed!");
+    0
+}
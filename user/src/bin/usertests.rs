@@ -6,11 +6,16 @@ extern crate user_lib;
 
 // not in SUCC_TESTS & FAIL_TESTS
 // count_lines, infloop, user_shell, usertests
+// priority: run by hand on a kernel built with SCHED=stride or SCHED=cfs, the others ignore priorities
+// sched_mlfq: run by hand on a kernel built with SCHED=mlfq
+// sched_cfs: run by hand on a kernel built with SCHED=cfs
+// switch_bench: a benchmark run by hand, it checks nothing
 
 // item of TESTS : app_name(argv_0), argv_1, argv_2, argv_3, exit_code
 static SUCC_TESTS: &[(&str, &str, &str, &str, i32)] = &[
     ("filetest_simple\0", "\0", "\0", "\0", 0),
     ("cat\0", "filea\0", "\0", "\0", 0),
+    ("brktest\0", "\0", "\0", "\0", 0),
     ("cmdline_args\0", "1\0", "2\0", "3\0", 0),
     ("exit\0", "\0", "\0", "\0", 0),
     ("fantastic_text\0", "\0", "\0", "\0", 0),
@@ -21,6 +26,13 @@ static SUCC_TESTS: &[(&str, &str, &str, &str, i32)] = &[
     ("hello_world\0", "\0", "\0", "\0", 0),
     ("huge_write\0", "\0", "\0", "\0", 0),
     ("matrix\0", "\0", "\0", "\0", 0),
+    ("mmap_filetest\0", "\0", "\0", "\0", 0),
+    ("mmaptest\0", "\0", "\0", "\0", 0),
+    ("munmap_blocked\0", "\0", "\0", "\0", 0),
+    ("oomtest\0", "\0", "\0", "\0", 0),
+    ("hugetest\0", "\0", "\0", "\0", 0),
+    ("zero_page\0", "\0", "\0", "\0", 0),
+    ("tlb_shootdown\0", "\0", "\0", "\0", 0),
     // ("mpsc_sem\0", "\0", "\0", "\0", 0),
     ("phil_din_mutex\0", "\0", "\0", "\0", 0),
     ("pipe_large_test\0", "\0", "\0", "\0", 0),
@@ -32,11 +44,14 @@ static SUCC_TESTS: &[(&str, &str, &str, &str, i32)] = &[
     ("eisenberg\0", "\0", "\0", "\0", 0),
     ("peterson\0", "\0", "\0", "\0", 0),
     ("run_pipe_test\0", "\0", "\0", "\0", 0),
+    ("shmtest\0", "\0", "\0", "\0", 0),
     ("sleep_simple\0", "\0", "\0", "\0", 0),
     ("sleep\0", "\0", "\0", "\0", 0),
     ("sleep_simple\0", "\0", "\0", "\0", 0),
+    ("stack_growth\0", "\0", "\0", "\0", 0),
     ("stackful_coroutine\0", "\0", "\0", "\0", 0),
     ("stackless_coroutine\0", "\0", "\0", "\0", 0),
+    ("swaptest\0", "\0", "\0", "\0", 0),
     // ("sync_sem\0", "\0", "\0", "\0", 0),
     // ("condsync_sem\0", "\0", "\0", "\0", 0),
     // ("condsync_condvar\0", "\0", "\0", "\0", 0),
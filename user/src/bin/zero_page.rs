@@ -0,0 +1,49 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{fork, mmap, munmap, wait, MmapProt};
+
+const PAGE_SIZE: usize = 4096;
+/// more than the physical memory, it only fits while the pages share the zero page
+const LEN: usize = 256 << 20;
+
+fn page(start: usize, page: usize) -> *mut usize {
+    (start + page * PAGE_SIZE) as *mut usize
+}
+
+#[unsafe(no_mangle)]
+pub fn main() -> i32 {
+    let start = mmap(0, LEN, MmapProt::READ | MmapProt::WRITE);
+    assert!(start > 0);
+    let start = start as usize;
+    for i in 0..LEN / PAGE_SIZE {
+        assert_eq!(unsafe { page(start, i).read_volatile() }, 0);
+    }
+    println!("read {} MiB of untouched memory", LEN >> 20);
+
+    // written pages get frames of their own
+    for i in (0..LEN / PAGE_SIZE).step_by(1024) {
+        unsafe { page(start, i).write_volatile(i) };
+    }
+    let pid = fork();
+    if pid == 0 {
+        for i in 0..LEN / PAGE_SIZE {
+            let expected = if i % 1024 == 0 { i } else { 0 };
+            assert_eq!(unsafe { page(start, i).read_volatile() }, expected);
+        }
+        unsafe { page(start, 1).write_volatile(1) };
+        return 0;
+    }
+    let mut exit_code = 0;
+    assert_eq!(wait(&mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    // the write of the child stays there
+    assert_eq!(unsafe { page(start, 1).read_volatile() }, 0);
+    assert_eq!(unsafe { page(start, 1024).read_volatile() }, 1024);
+    assert_eq!(munmap(start, LEN), 0);
+    println!("zero_page passed!");
+    0
+}
@@ -14,7 +14,6 @@ mod lang_items;
 #[unsafe(no_mangle)]
 #[unsafe(link_section = ".text.entry")]
 pub extern "C" fn _start(argc: usize, argv: usize) -> ! {
-    init_heap();
     let mut v: Vec<&'static str> = Vec::new();
     for i in 0..argc {
         let str_start = unsafe {
@@ -41,31 +40,34 @@ fn main(_argc: usize, _argv: &[&str]) -> i32 {
 
 use alloc::vec::Vec;
 use core::alloc::Layout;
-use core::ptr::addr_of_mut;
 use bitflags::bitflags;
-use buddy_allocator::LockedBuddyAllocator;
+use buddy_allocator::{BuddyAllocator, LockedBuddyAllocator};
 
-const USER_HEAP_SIZE: usize = 32768;
-
-static mut USER_HEAP_SPACE: [u8; USER_HEAP_SIZE] = [0; USER_HEAP_SIZE];
+/// the heap grows by at least this size when it runs out
+const USER_HEAP_GROW_SIZE: usize = 32768;
 
 #[global_allocator]
-static HEAP_ALLOCATOR: LockedBuddyAllocator = LockedBuddyAllocator::empty();
+static HEAP_ALLOCATOR: LockedBuddyAllocator = LockedBuddyAllocator::with_rescue(grow_heap);
 
 #[alloc_error_handler]
 pub fn handle_alloc_error(layout: Layout) -> ! {
     panic!("Heap allocation error: layout = {:?}", layout);
 }
 
-fn init_heap() {
-    unsafe {
-        HEAP_ALLOCATOR
-            .lock()
-            .init(addr_of_mut!(USER_HEAP_SPACE) as usize, USER_HEAP_SIZE);
+fn grow_heap(heap: &mut BuddyAllocator, layout: &Layout) {
+    // twice the block size leaves room for an aligned block and the bitmap of the range,
+    // doubling the heap keeps the number of ranges small
+    let size = (layout.size().max(layout.align()).next_power_of_two() * 2)
+        .max(USER_HEAP_GROW_SIZE)
+        .max(heap.stats().total);
+    let start = sbrk(size as isize);
+    if start != -1 {
+        unsafe {
+            heap.add_to_heap(start as usize, start as usize + size);
+        }
     }
 }
 
-
 bitflags! {
     pub struct OpenFlags: u32 {
         const RDONLY = 0;
@@ -76,6 +78,30 @@ bitflags! {
     }
 }
 
+bitflags! {
+    pub struct MmapProt: u32 {
+        const READ = 1 << 0;
+        const WRITE = 1 << 1;
+        const EXEC = 1 << 2;
+    }
+}
+
+bitflags! {
+    pub struct MmapFlags: u32 {
+        const SHARED = 0x01;
+        const PRIVATE = 0x02;
+        const ANONYMOUS = 0x20;
+        const HUGETLB = 0x40000;
+    }
+}
+
+/// shmget key that always creates a new segment
+pub const IPC_PRIVATE: usize = 0;
+/// shmctl command removing a segment
+pub const IPC_RMID: usize = 0;
+/// shmat flag attaching a segment read-only
+pub const SHM_RDONLY: usize = 0o10000;
+
 pub const SIGDEF: i32 = 0; // Default signal handling
 pub const SIGHUP: i32 = 1;
 pub const SIGINT: i32 = 2;
@@ -189,10 +215,35 @@ pub fn sigaction(
 }
 pub fn sigprocmask(mask: u32) -> isize { sys_sigprocmask(mask) }
 pub fn sigreturn() -> isize { sys_sigreturn() }
+pub fn set_priority(prio: isize) -> isize { sys_set_priority(prio) }
 pub fn get_time() -> isize { sys_get_time() }
 pub fn getpid() -> isize { sys_getpid() }
 pub fn fork() -> isize { sys_fork() }
 pub fn exec(path: &str, args: &[*const u8]) -> isize { sys_exec(path, args) }
+pub fn mmap(start: usize, len: usize, prot: MmapProt) -> isize {
+    sys_mmap(start, len, prot.bits, (MmapFlags::PRIVATE | MmapFlags::ANONYMOUS).bits, usize::MAX, 0)
+}
+pub fn mmap_huge(start: usize, len: usize, prot: MmapProt) -> isize {
+    let flags = MmapFlags::PRIVATE | MmapFlags::ANONYMOUS | MmapFlags::HUGETLB;
+    sys_mmap(start, len, prot.bits, flags.bits, usize::MAX, 0)
+}
+pub fn mmap_file(start: usize, len: usize, prot: MmapProt, flags: MmapFlags, fd: usize, offset: usize) -> isize {
+    sys_mmap(start, len, prot.bits, flags.bits, fd, offset)
+}
+pub fn munmap(start: usize, len: usize) -> isize { sys_munmap(start, len) }
+pub fn mprotect(start: usize, len: usize, prot: MmapProt) -> isize { sys_mprotect(start, len, prot.bits) }
+pub fn msync(start: usize, len: usize) -> isize { sys_msync(start, len) }
+pub fn shmget(key: usize, size: usize) -> isize { sys_shmget(key, size) }
+pub fn shmat(shmid: usize, start: usize, shmflg: usize) -> isize { sys_shmat(shmid, start, shmflg) }
+pub fn shmdt(start: usize) -> isize { sys_shmdt(start) }
+pub fn shmctl(shmid: usize, cmd: usize) -> isize { sys_shmctl(shmid, cmd) }
+pub fn brk(addr: usize) -> isize { sys_brk(addr) }
+/// Move the program break by `increment`, return the old break or -1 on failure
+pub fn sbrk(increment: isize) -> isize {
+    let old_brk = sys_brk(0);
+    let new_brk = old_brk + increment;
+    if sys_brk(new_brk as usize) == new_brk { old_brk } else { -1 }
+}
 pub fn wait(exit_code: &mut i32) -> isize {
     loop {
         match sys_waitpid(-1, exit_code as *mut _) {
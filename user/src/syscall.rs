@@ -15,6 +15,23 @@ fn syscall(id: usize, args: [usize; 3]) -> isize {
     ret
 }
 
+fn syscall6(id: usize, args: [usize; 6]) -> isize {
+    let mut ret: isize;
+    unsafe {
+        asm!(
+            "ecall",
+            inlateout("x10") args[0] => ret,
+            in("x11") args[1],
+            in("x12") args[2],
+            in("x13") args[3],
+            in("x14") args[4],
+            in("x15") args[5],
+            in("x17") id
+        );
+    }
+    ret
+}
+
 const SYSCALL_DUP: usize = 24;
 const SYSCALL_OPEN: usize = 56;
 const SYSCALL_CLOSE: usize = 57;
@@ -28,10 +45,20 @@ const SYSCALL_KILL: usize = 129;
 const SYSCALL_SIGACTION: usize = 134;
 const SYSCALL_SIGPROCMASK: usize = 135;
 const SYSCALL_RETURN: usize = 139;
+const SYSCALL_SET_PRIORITY: usize = 140;
 const SYSCALL_GETTIME: usize = 169;
 const SYSCALL_GETPID: usize = 172;
+const SYSCALL_SHMGET: usize = 194;
+const SYSCALL_SHMCTL: usize = 195;
+const SYSCALL_SHMAT: usize = 196;
+const SYSCALL_SHMDT: usize = 197;
+const SYSCALL_BRK: usize = 214;
+const SYSCALL_MUNMAP: usize = 215;
 const SYSCALL_FORK: usize = 220;
 const SYSCALL_EXEC: usize = 221;
+const SYSCALL_MMAP: usize = 222;
+const SYSCALL_MPROTECT: usize = 226;
+const SYSCALL_MSYNC: usize = 227;
 const SYSCALL_WAITPID: usize = 260;
 const SYSCALL_THREAD_CREATE: usize = 1000;
 const SYSCALL_GETTID: usize = 1001;
@@ -93,6 +120,10 @@ pub fn sys_sigreturn() -> isize {
     syscall(SYSCALL_RETURN, [0, 0, 0])
 }
 
+pub fn sys_set_priority(prio: isize) -> isize {
+    syscall(SYSCALL_SET_PRIORITY, [prio as usize, 0, 0])
+}
+
 pub fn sys_get_time() -> isize {
     syscall(SYSCALL_GETTIME, [0, 0, 0])
 }
@@ -109,6 +140,42 @@ pub fn sys_exec(path: &str, args: &[*const u8]) -> isize {
     syscall(SYSCALL_EXEC, [path.as_ptr() as usize, args.as_ptr() as usize, 0])
 }
 
+pub fn sys_mmap(start: usize, len: usize, prot: u32, flags: u32, fd: usize, offset: usize) -> isize {
+    syscall6(SYSCALL_MMAP, [start, len, prot as usize, flags as usize, fd, offset])
+}
+
+pub fn sys_shmget(key: usize, size: usize) -> isize {
+    syscall(SYSCALL_SHMGET, [key, size, 0])
+}
+
+pub fn sys_shmctl(shmid: usize, cmd: usize) -> isize {
+    syscall(SYSCALL_SHMCTL, [shmid, cmd, 0])
+}
+
+pub fn sys_shmat(shmid: usize, start: usize, shmflg: usize) -> isize {
+    syscall(SYSCALL_SHMAT, [shmid, start, shmflg])
+}
+
+pub fn sys_shmdt(start: usize) -> isize {
+    syscall(SYSCALL_SHMDT, [start, 0, 0])
+}
+
+pub fn sys_brk(addr: usize) -> isize {
+    syscall(SYSCALL_BRK, [addr, 0, 0])
+}
+
+pub fn sys_munmap(start: usize, len: usize) -> isize {
+    syscall(SYSCALL_MUNMAP, [start, len, 0])
+}
+
+pub fn sys_mprotect(start: usize, len: usize, prot: u32) -> isize {
+    syscall(SYSCALL_MPROTECT, [start, len, prot as usize])
+}
+
+pub fn sys_msync(start: usize, len: usize) -> isize {
+    syscall(SYSCALL_MSYNC, [start, len, 0])
+}
+
 pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
     syscall(SYSCALL_WAITPID, [pid as usize, exit_code_ptr as usize, 0])
 }